@@ -0,0 +1,74 @@
+//Burn backends the model can run on, each behind the cargo feature of the same name.
+//The binaries enable them through their own features and run on the one `select_backend!` picks.
+
+/// Pure Rust CPU backend, needs no GPU driver.
+#[cfg(feature = "ndarray")]
+pub mod ndarray {
+    use burn::backend::ndarray::NdArrayDevice;
+
+    pub type Backend = burn::backend::NdArray<f32>;
+    pub const NAME: &str = "ndarray";
+
+    pub fn device() -> NdArrayDevice {
+        NdArrayDevice::Cpu
+    }
+}
+
+/// GPU backend over Vulkan, Metal or DX12.
+#[cfg(feature = "wgpu")]
+pub mod wgpu {
+    use burn::backend::wgpu::WgpuDevice;
+
+    pub type Backend = burn::backend::Wgpu<f32, i32>;
+    pub const NAME: &str = "wgpu";
+
+    pub fn device() -> WgpuDevice {
+        WgpuDevice::default()
+    }
+}
+
+/// Candle backend, on the CPU.
+#[cfg(feature = "candle")]
+pub mod candle {
+    use burn::backend::candle::CandleDevice;
+
+    pub type Backend = burn::backend::Candle<f32, i64>;
+    pub const NAME: &str = "candle";
+
+    pub fn device() -> CandleDevice {
+        CandleDevice::Cpu
+    }
+}
+
+/// LibTorch backend, on the CPU. Needs libtorch installed, see the tch crate.
+#[cfg(feature = "tch")]
+pub mod tch {
+    use burn::backend::libtorch::LibTorchDevice;
+
+    pub type Backend = burn::backend::LibTorch<f32>;
+    pub const NAME: &str = "tch";
+
+    pub fn device() -> LibTorchDevice {
+        LibTorchDevice::Cpu
+    }
+}
+
+/// Re-export the backend the calling crate runs on, chosen by its own cargo features: wgpu, candle
+/// and tch take precedence over ndarray, so enabling one of them is enough to switch. The features
+/// of this crate are unified across the workspace, so they cannot tell which backend a binary wants.
+#[macro_export]
+macro_rules! select_backend {
+    () => {
+        #[cfg(feature = "wgpu")]
+        pub use $crate::backend::wgpu::*;
+        #[cfg(all(feature = "candle", not(feature = "wgpu")))]
+        pub use $crate::backend::candle::*;
+        #[cfg(all(feature = "tch", not(any(feature = "wgpu", feature = "candle"))))]
+        pub use $crate::backend::tch::*;
+        #[cfg(all(feature = "ndarray", not(any(feature = "wgpu", feature = "candle", feature = "tch"))))]
+        pub use $crate::backend::ndarray::*;
+
+        #[cfg(not(any(feature = "ndarray", feature = "wgpu", feature = "candle", feature = "tch")))]
+        compile_error!("enable one of the ndarray, wgpu, candle or tch features");
+    };
+}
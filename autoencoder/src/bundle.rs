@@ -0,0 +1,166 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context};
+use burn::{
+    module::Module,
+    prelude::Backend,
+    record::{BinBytesRecorder, FullPrecisionSettings, Recorder},
+};
+use ebsentinel_features::{pipeline::Preprocessing, selection::Selection, syscall_table_hash};
+use serde::{Deserialize, Serialize};
+
+use crate::{calibration::Calibration, registry::Provenance, Model, ModelConfig};
+
+/// File name of the bundle in a run directory.
+pub const BUNDLE: &str = "model.ebsentinel";
+/// Version of the layout below, bumped whenever a reader of the previous one cannot read it.
+pub const FORMAT_VERSION: u32 = 2;
+const MAGIC: &[u8; 8] = b"EBSMODEL";
+const HEADER: usize = MAGIC.len() + 4 + 8;
+
+/// Everything the detector needs to know about a model besides its weights.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub model: ModelConfig,
+    /// Features derived from the raw counters, None for the ones the sensor computes.
+    pub preprocessing: Option<Preprocessing>,
+    /// Features the model takes, None for all of them. Added in version 2.
+    pub selection: Option<Selection>,
+    pub calibration: Calibration,
+    /// Architecture the training samples were recorded on.
+    pub arch: String,
+    /// Syscall table the features are indexed by, see [`syscall_table_hash`].
+    pub syscall_table_hash: String,
+    pub data: Option<Provenance>,
+}
+
+impl Manifest {
+    /// Fails unless the model takes `input_size` features indexed by the syscall numbers of this host.
+    pub fn check(&self, input_size: usize) -> anyhow::Result<()> {
+        let expected = match &self.selection {
+            Some(selection) if selection.kept.len() != self.model.input_size => {
+                bail!("the model takes {} features but {} are selected", self.model.input_size, selection.kept.len())
+            }
+            Some(selection) => selection.input_size,
+            None => self.model.input_size,
+        };
+        if expected != input_size {
+            bail!("the model takes {expected} features but this host produces {input_size}");
+        }
+        if self.syscall_table_hash != syscall_table_hash() {
+            bail!(
+                "the model was trained on {} samples whose syscall numbers differ from {}",
+                self.arch,
+                std::env::consts::ARCH
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A model with its manifest, saved as a single file: the magic bytes, the format version
+/// (u32) and the length of the manifest (u64) in little endian, the manifest in JSON, then the
+/// weights.
+pub struct Bundle<B: Backend> {
+    pub manifest: Manifest,
+    pub model: Model<B>,
+}
+
+impl<B: Backend> Bundle<B> {
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let manifest = serde_json::to_vec(&self.manifest)?;
+        let weights = BinBytesRecorder::<FullPrecisionSettings>::default().record(self.model.clone().into_record(), ())?;
+        let mut bytes = Vec::with_capacity(HEADER + manifest.len() + weights.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(manifest.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&manifest);
+        bytes.extend_from_slice(&weights);
+        fs::write(path, bytes).with_context(|| format!("cannot write {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>, device: &B::Device) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+        Self::from_bytes(&bytes, device).with_context(|| format!("invalid model bundle {}", path.display()))
+    }
+
+    /// The bundle of the content of a file, e.g. once its signature is verified.
+    pub fn from_bytes(bytes: &[u8], device: &B::Device) -> anyhow::Result<Self> {
+        let (manifest, weights) = split(bytes)?;
+        let record = BinBytesRecorder::<FullPrecisionSettings>::default()
+            .load(weights.to_vec(), device)
+            .context("the weights do not fit the model")?;
+        let model = manifest.model.init::<B>(device).load_record(record);
+        Ok(Self { manifest, model })
+    }
+}
+
+/// The manifest of a bundle, without loading its weights.
+pub fn manifest(path: impl AsRef<Path>) -> anyhow::Result<Manifest> {
+    let path = path.as_ref();
+    let bytes = fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+    Ok(split(&bytes).with_context(|| format!("invalid model bundle {}", path.display()))?.0)
+}
+
+//The manifest and the weights of a bundle.
+fn split(bytes: &[u8]) -> anyhow::Result<(Manifest, &[u8])> {
+    if bytes.len() < HEADER || &bytes[..MAGIC.len()] != MAGIC {
+        bail!("not a model bundle");
+    }
+    let version = u32::from_le_bytes(bytes[8..12].try_into()?);
+    if version > FORMAT_VERSION {
+        bail!("bundle format {version} is newer than the supported {FORMAT_VERSION}, update ebsentinel");
+    }
+    let length = u64::from_le_bytes(bytes[12..HEADER].try_into()?) as usize;
+    let Some(manifest) = bytes[HEADER..].get(..length) else {
+        bail!("truncated manifest");
+    };
+    Ok((serde_json::from_slice(manifest)?, &bytes[HEADER + length..]))
+}
+
+#[cfg(test)]
+mod test {
+    use ebsentinel_features::{selection::Selection, syscall_table_hash};
+
+    use super::{split, Manifest, FORMAT_VERSION, HEADER, MAGIC};
+    use crate::{calibration::Calibration, ModelConfig};
+
+    fn bundle(version: u32, manifest: &Manifest) -> Vec<u8> {
+        let json = serde_json::to_vec(manifest).unwrap();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(&(json.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&json);
+        bytes.extend_from_slice(b"weights");
+        bytes
+    }
+
+    #[test]
+    fn reads_and_checks_manifests() {
+        let manifest = Manifest {
+            model: ModelConfig::new(512, 64),
+            preprocessing: None,
+            selection: None,
+            calibration: Calibration::new("max".parse().unwrap(), "valid", &[0.1, 0.2]).unwrap(),
+            arch: std::env::consts::ARCH.to_string(),
+            syscall_table_hash: syscall_table_hash(),
+            data: None,
+        };
+        let bytes = bundle(FORMAT_VERSION, &manifest);
+        let (read, weights) = split(&bytes).unwrap();
+        assert_eq!((read.calibration.threshold, weights), (0.2, &b"weights"[..]));
+        assert!(read.check(512).is_ok());
+        assert!(read.check(456).is_err());
+        let foreign = Manifest { syscall_table_hash: "0".repeat(64), ..manifest.clone() };
+        assert!(foreign.check(512).is_err());
+        let selected = Manifest { model: ModelConfig::new(2, 1), selection: Some(Selection { input_size: 512, kept: vec![0, 3] }), ..manifest.clone() };
+        assert!(selected.check(512).is_ok());
+        assert!(Manifest { model: ModelConfig::new(3, 1), ..selected }.check(512).is_err());
+
+        assert!(split(&bundle(FORMAT_VERSION + 1, &manifest)).is_err());
+        assert!(split(&bytes[..HEADER + 4]).is_err());
+        assert!(split(b"EBSMODEX").is_err());
+    }
+}
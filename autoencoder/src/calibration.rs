@@ -0,0 +1,219 @@
+use std::{fmt, fs, path::Path, str::FromStr};
+
+use anyhow::{anyhow, bail, Context};
+use serde::{Deserialize, Serialize};
+
+//Quantiles of the scores kept with the threshold.
+const QUANTILES: [f64; 5] = [0.5, 0.9, 0.95, 0.99, 0.999];
+const HISTOGRAM_BINS: usize = 50;
+//Share of the highest scores the POT tail is fitted on.
+const POT_TAIL: f64 = 0.02;
+
+/// How the anomaly threshold is derived from the reconstruction losses of normal samples.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "kebab-case")]
+pub enum Strategy {
+    /// The highest loss.
+    Max,
+    /// The `p`th percentile of the losses.
+    Percentile { p: f64 },
+    /// The mean loss plus `k` standard deviations.
+    MeanStd { k: f64 },
+    /// Peaks over threshold: the tail of the losses is fitted with a generalized Pareto
+    /// distribution, a normal sample exceeds the threshold with probability `risk`.
+    Pot { risk: f64 },
+}
+
+impl FromStr for Strategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once(':') {
+            Some((name, param)) => (name, Some(param.trim().parse::<f64>()?)),
+            None => (s, None),
+        };
+        let strategy = match (name, param) {
+            ("max", None) => Self::Max,
+            ("percentile", Some(p)) if p > 0.0 && p <= 100.0 => Self::Percentile { p },
+            ("mean-std", Some(k)) if k.is_finite() => Self::MeanStd { k },
+            ("pot", Some(risk)) if risk > 0.0 && risk < 1.0 => Self::Pot { risk },
+            _ => return Err(anyhow!("unknown calibration {s}, expected max, percentile:P, mean-std:K or pot:RISK")),
+        };
+        Ok(strategy)
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Max => f.write_str("max"),
+            Self::Percentile { p } => write!(f, "percentile:{p}"),
+            Self::MeanStd { k } => write!(f, "mean-std:{k}"),
+            Self::Pot { risk } => write!(f, "pot:{risk}"),
+        }
+    }
+}
+
+impl Strategy {
+    /// The threshold of losses sorted in increasing order.
+    fn threshold(&self, sorted: &[f64]) -> f64 {
+        match *self {
+            Self::Max => sorted[sorted.len() - 1],
+            Self::Percentile { p } => quantile(sorted, p / 100.0),
+            Self::MeanStd { k } => {
+                let (mean, std) = mean_std(sorted);
+                mean + k * std
+            }
+            Self::Pot { risk } => pot(sorted, risk),
+        }
+    }
+}
+
+/// A calibrated threshold with the losses it was derived from, saved next to the model.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Calibration {
+    pub strategy: Strategy,
+    /// Split of the database the losses were computed on.
+    pub split: String,
+    pub threshold: f32,
+    pub scores: Scores,
+}
+
+/// Summary of the distribution of the losses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scores {
+    pub count: usize,
+    pub mean: f64,
+    pub std: f64,
+    pub min: f64,
+    pub max: f64,
+    /// `(q, loss)` pairs.
+    pub quantiles: Vec<(f64, f64)>,
+    pub histogram: Histogram,
+}
+
+/// Counts of the losses in equal-width bins from `low` to `high`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub low: f64,
+    pub high: f64,
+    pub counts: Vec<usize>,
+}
+
+impl Calibration {
+    /// Calibrate the threshold on the losses of the samples of `split`.
+    pub fn new(strategy: Strategy, split: &str, scores: &[f32]) -> anyhow::Result<Self> {
+        if scores.is_empty() {
+            bail!("cannot calibrate the threshold without samples in {split}");
+        }
+        if scores.iter().any(|score| !score.is_finite()) {
+            bail!("the losses of {split} are not all finite");
+        }
+        let mut sorted: Vec<f64> = scores.iter().map(|score| *score as f64).collect();
+        sorted.sort_by(f64::total_cmp);
+        let (mean, std) = mean_std(&sorted);
+        let (low, high) = (sorted[0], sorted[sorted.len() - 1]);
+
+        let mut counts = vec![0; HISTOGRAM_BINS];
+        for score in &sorted {
+            let bin = if high > low { ((score - low) / (high - low) * HISTOGRAM_BINS as f64) as usize } else { 0 };
+            counts[bin.min(HISTOGRAM_BINS - 1)] += 1;
+        }
+
+        Ok(Self {
+            strategy,
+            split: split.to_string(),
+            threshold: strategy.threshold(&sorted) as f32,
+            scores: Scores {
+                count: sorted.len(),
+                mean,
+                std,
+                min: low,
+                max: high,
+                quantiles: QUANTILES.iter().map(|q| (*q, quantile(&sorted, *q))).collect(),
+                histogram: Histogram { low, high, counts },
+            },
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        fs::write(path, serde_json::to_string_pretty(self)?).with_context(|| format!("cannot write {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+}
+
+//Nearest-rank quantile, `q` in [0, 1].
+fn quantile(sorted: &[f64], q: f64) -> f64 {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn mean_std(values: &[f64]) -> (f64, f64) {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    (mean, variance.sqrt())
+}
+
+//Fits a generalized Pareto distribution on the excesses over a high quantile by the method of
+//moments, then solves for the loss exceeded with probability `risk` (Siffer et al., KDD 2017).
+fn pot(sorted: &[f64], risk: f64) -> f64 {
+    let initial = quantile(sorted, 1.0 - POT_TAIL);
+    let excesses: Vec<f64> = sorted.iter().filter(|score| **score > initial).map(|score| score - initial).collect();
+    let (mean, std) = if excesses.len() >= 2 { mean_std(&excesses) } else { (0.0, 0.0) };
+    //Too few distinct peaks to fit a tail.
+    if std == 0.0 {
+        return sorted[sorted.len() - 1];
+    }
+    let ratio = mean * mean / (std * std);
+    let gamma = 0.5 * (1.0 - ratio);
+    let sigma = 0.5 * mean * (ratio + 1.0);
+    let exceeded = risk * sorted.len() as f64 / excesses.len() as f64;
+    if gamma.abs() < 1e-9 {
+        initial - sigma * exceeded.ln()
+    } else {
+        initial + sigma / gamma * (exceeded.powf(-gamma) - 1.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Calibration, Strategy};
+
+    #[test]
+    fn parses_strategies() {
+        for s in ["max", "percentile:99.5", "mean-std:3", "pot:0.0001"] {
+            assert_eq!(s.parse::<Strategy>().unwrap().to_string(), s);
+        }
+        assert!("percentile:120".parse::<Strategy>().is_err());
+        assert!("pot".parse::<Strategy>().is_err());
+    }
+
+    #[test]
+    fn thresholds_of_each_strategy() {
+        let scores: Vec<f32> = (1..=100).map(|score| score as f32).collect();
+        let threshold = |strategy: &str| Calibration::new(strategy.parse().unwrap(), "valid", &scores).unwrap().threshold;
+        assert_eq!(threshold("max"), 100.0);
+        assert_eq!(threshold("percentile:90"), 90.0);
+        assert!((threshold("mean-std:1") - (50.5 + 28.866)).abs() < 0.01);
+
+        let calibration = Calibration::new(Strategy::Max, "valid", &scores).unwrap();
+        assert_eq!(calibration.scores.histogram.counts.iter().sum::<usize>(), 100);
+        assert!(Calibration::new(Strategy::Max, "valid", &[]).is_err());
+    }
+
+    #[test]
+    fn pot_extrapolates_the_tail() {
+        //Quantiles of an exponential distribution, whose 1 - risk quantile is -ln(risk).
+        let n = 1000;
+        let scores: Vec<f32> = (1..=n).map(|i| -(1.0 - i as f64 / (n + 1) as f64).ln() as f32).collect();
+        let threshold = |risk: f64| Calibration::new(Strategy::Pot { risk }, "valid", &scores).unwrap().threshold;
+        assert!((threshold(0.01) - 0.01f32.ln().abs()).abs() < 0.3);
+        assert!(threshold(0.0001) > scores[n - 1]);
+    }
+}
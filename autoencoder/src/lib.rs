@@ -4,7 +4,12 @@ use burn::prelude::*;
 use burn::tensor::cast::ToElement;
 use burn::data::dataloader::batcher::Batcher;
 use data::{SyscallBatcher, Syscalls};
+pub mod backend;
+pub mod bundle;
+pub mod calibration;
 pub mod data;
+pub mod registry;
+pub mod signing;
 
 #[derive(Module, Debug)]
 pub struct Encoder<B: Backend> {
@@ -89,15 +94,18 @@ impl<B: Backend> Autoencoder<B> {
 pub struct AutoencoderConfig {
     input_size: usize,
     latent_size: usize,
+    /// Width of the hidden layers, half the input by default.
+    hidden_size: Option<usize>,
 }
 
 impl AutoencoderConfig {
     pub fn init<B: Backend>(&self, device: &B::Device) -> Autoencoder<B> {
+        let hidden_size = self.hidden_size.unwrap_or(self.input_size / 2);
         Autoencoder {
             encoder: Encoder {
-                linear: LinearConfig::new(self.input_size, self.input_size/2).init(device),
+                linear: LinearConfig::new(self.input_size, hidden_size).init(device),
                 activation: Relu::new(),
-                linear2: LinearConfig::new(self.input_size/2, self.latent_size).init(device),
+                linear2: LinearConfig::new(hidden_size, self.latent_size).init(device),
                 activation2: Relu::new(),
 
             },
@@ -108,9 +116,9 @@ impl AutoencoderConfig {
                 activation: Relu::new(),
             },
             decoder: Decoder {
-                linear: LinearConfig::new(self.latent_size, self.input_size/2).init(device),
+                linear: LinearConfig::new(self.latent_size, hidden_size).init(device),
                 activation: Relu::new(),
-                linear2: LinearConfig::new(self.input_size/2, self.input_size).init(device),
+                linear2: LinearConfig::new(hidden_size, self.input_size).init(device),
                 activation2: Relu::new(),
             },
         }
@@ -122,16 +130,58 @@ pub struct Model<B: Backend> {
     pub inner: Autoencoder<B>,
 }
 
+impl<B: Backend> Model<B> {
+    pub fn forward(&self, vecs: Tensor<B, 2>) -> Tensor<B, 2> {
+        self.inner.forward(vecs)
+    }
+}
+
+/// Architecture of the model, shared by the trainer, the bundles and the detector.
 #[derive(Config, Debug)]
 pub struct ModelConfig {
-    input_size: usize,
-    latent_size: usize,
+    pub input_size: usize,
+    pub latent_size: usize,
+    /// Width of the hidden layers, half the input by default.
+    pub hidden_size: Option<usize>,
 }
 
 impl ModelConfig {
     pub fn init<B: Backend>(&self, device: &B::Device) -> Model<B> {
-        let inner = AutoencoderConfig::new(self.input_size, self.latent_size).init(device);
+        let inner = AutoencoderConfig::new(self.input_size, self.latent_size).with_hidden_size(self.hidden_size).init(device);
         Model { inner }
     }
 }
 
+//Only the trainer needs the learner steps, and burn's train feature with them.
+#[cfg(feature = "train")]
+mod train {
+    use burn::{
+        nn::loss::{MseLoss, Reduction},
+        prelude::*,
+        tensor::backend::AutodiffBackend,
+        train::{RegressionOutput, TrainOutput, TrainStep, ValidStep},
+    };
+
+    use crate::{data::SyscallBatch, Model};
+
+    impl<B: Backend> Model<B> {
+        pub fn forward_reconstruction(&self, vecs: Tensor<B, 2>) -> RegressionOutput<B> {
+            let output = self.inner.forward(vecs.clone());
+            let loss: Tensor<B, 1> = MseLoss::new().forward(output.clone(), vecs.clone(), Reduction::Mean);
+            RegressionOutput::new(loss, output, vecs)
+        }
+    }
+
+    impl<B: AutodiffBackend> TrainStep<SyscallBatch<B>, RegressionOutput<B>> for Model<B> {
+        fn step(&self, batch: SyscallBatch<B>) -> TrainOutput<RegressionOutput<B>> {
+            let item = self.forward_reconstruction(batch.syscalls);
+            TrainOutput::new(self, item.loss.backward(), item)
+        }
+    }
+
+    impl<B: Backend> ValidStep<SyscallBatch<B>, RegressionOutput<B>> for Model<B> {
+        fn step(&self, batch: SyscallBatch<B>) -> RegressionOutput<B> {
+            self.forward_reconstruction(batch.syscalls)
+        }
+    }
+}
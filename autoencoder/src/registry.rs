@@ -0,0 +1,319 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+/// Index of the runs, in the root of the artifact dir.
+pub const INDEX: &str = "registry.json";
+//Held while the index is changed, by every process sharing the artifact dir.
+const LOCK: &str = "registry.lock";
+const RUNS: &str = "runs";
+
+/// A training run, its model lives in `runs/<name>` under the artifact dir.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Run {
+    pub name: String,
+    /// Unix time in seconds.
+    pub created_at: i64,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// e.g. `threshold`, `valid_loss`, or the `roc_auc` of the last evaluation.
+    #[serde(default)]
+    pub metrics: BTreeMap<String, f64>,
+    pub data: Option<Provenance>,
+    /// Run whose model this one was fine-tuned from.
+    pub base: Option<String>,
+}
+
+/// The samples a run was trained on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Provenance {
+    pub db: String,
+    /// SHA-256 of the database file when the training ended.
+    pub sha256: String,
+    /// Number of samples of each split.
+    pub samples: BTreeMap<String, usize>,
+    pub sessions: Vec<String>,
+    pub preprocessing: String,
+    pub syscall_table_hash: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    runs: Vec<Run>,
+    promoted: Option<String>,
+}
+
+impl Index {
+    fn load(root: &Path) -> anyhow::Result<Self> {
+        let path = root.join(INDEX);
+        match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).with_context(|| format!("invalid registry {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("cannot read {}", path.display())),
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<&Run> {
+        self.runs.iter().find(|run| run.name == name)
+    }
+
+    fn resolve(&self, selector: &str, root: &Path) -> anyhow::Result<&Run> {
+        let latest = |tag: Option<&str>| {
+            self.runs
+                .iter()
+                .filter(|run| tag.is_none_or(|tag| run.tags.iter().any(|t| t == tag)))
+                .max_by_key(|run| run.created_at)
+        };
+        let run = match selector {
+            "latest" => latest(None),
+            "promoted" => self.promoted.as_deref().and_then(|name| self.find(name)),
+            _ => self.find(selector).or_else(|| latest(Some(selector))),
+        };
+        run.with_context(|| format!("no run named or tagged {selector} in {}", root.display()))
+    }
+}
+
+/// The runs kept under an artifact dir.
+pub struct Registry {
+    root: PathBuf,
+    index: Index,
+}
+
+impl Registry {
+    /// Open the registry of `root`, empty if nothing was trained there yet.
+    pub fn open(root: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let index = Index::load(&root)?;
+        Ok(Self { root, index })
+    }
+
+    /// Runs in creation order.
+    pub fn runs(&self) -> &[Run] {
+        &self.index.runs
+    }
+
+    pub fn promoted(&self) -> Option<&str> {
+        self.index.promoted.as_deref()
+    }
+
+    pub fn dir(&self, name: &str) -> PathBuf {
+        self.root.join(RUNS).join(name)
+    }
+
+    /// Create the directory of a new run, named after the current UTC time. The run is only
+    /// recorded by [`Registry::add`], once its directory is complete, else its directory is
+    /// removed with [`Registry::discard`].
+    pub fn create_run(&self, tags: Vec<String>) -> anyhow::Result<(Run, PathBuf)> {
+        let created_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let base = utc(created_at);
+        let runs = self.root.join(RUNS);
+        fs::create_dir_all(&runs).with_context(|| format!("cannot create {}", runs.display()))?;
+        let mut n = 1;
+        loop {
+            let name = if n == 1 { base.clone() } else { format!("{base}-{n}") };
+            let dir = runs.join(&name);
+            //Fails if another run, possibly of another process, took the name: runs started within
+            //the same second get a suffix.
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok((Run { name, created_at, tags, metrics: BTreeMap::new(), data: None, base: None }, dir)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(e).with_context(|| format!("cannot create {}", dir.display())),
+            }
+        }
+    }
+
+    /// Remove the directory of a run that failed before being added.
+    pub fn discard(&self, run: &Run) -> anyhow::Result<()> {
+        let dir = self.dir(&run.name);
+        fs::remove_dir_all(&dir).with_context(|| format!("cannot remove {}", dir.display()))
+    }
+
+    pub fn add(&mut self, run: Run) -> anyhow::Result<()> {
+        self.update(|index| {
+            if index.find(&run.name).is_some() {
+                bail!("run {} already exists", run.name);
+            }
+            index.runs.push(run);
+            Ok(())
+        })
+    }
+
+    /// The run `selector` picks: `latest`, `promoted`, a run name, or the latest run with that tag.
+    pub fn resolve(&self, selector: &str) -> anyhow::Result<&Run> {
+        self.index.resolve(selector, &self.root)
+    }
+
+    /// The run `selector` picks, by default the promoted run, else the latest one. None for an
+    /// artifact dir holding a model and no runs, as trained before the registry existed.
+    pub fn select(&self, selector: Option<&str>) -> anyhow::Result<Option<&Run>> {
+        if selector.is_none() && self.index.runs.is_empty() && self.root.join("config.json").exists() {
+            return Ok(None);
+        }
+        let selector = selector.unwrap_or(if self.promoted().is_some() { "promoted" } else { "latest" });
+        self.resolve(selector).map(Some)
+    }
+
+    /// Directory of the model [`Registry::select`] picks.
+    pub fn model_dir(&self, selector: Option<&str>) -> anyhow::Result<PathBuf> {
+        Ok(match self.select(selector)? {
+            Some(run) => self.dir(&run.name),
+            None => self.root.clone(),
+        })
+    }
+
+    /// Make the run `selector` picks the default one of the detector.
+    pub fn promote(&mut self, selector: &str) -> anyhow::Result<String> {
+        let root = self.root.clone();
+        self.update(|index| {
+            let name = index.resolve(selector, &root)?.name.clone();
+            index.promoted = Some(name.clone());
+            Ok(name)
+        })
+    }
+
+    /// Merge `metrics` into the metrics of the run `name`.
+    pub fn set_metrics(&mut self, name: &str, metrics: BTreeMap<String, f64>) -> anyhow::Result<()> {
+        self.update(|index| {
+            let run = index.runs.iter_mut().find(|run| run.name == name).with_context(|| format!("no run named {name}"))?;
+            run.metrics.extend(metrics);
+            Ok(())
+        })
+    }
+
+    /// Delete all but the `keep` latest runs. The promoted run, and the tagged ones if `keep_tagged`, are always kept.
+    pub fn prune(&mut self, keep: usize, keep_tagged: bool) -> anyhow::Result<Vec<String>> {
+        let runs = self.root.join(RUNS);
+        self.update(|index| {
+            let mut newest: Vec<&Run> = index.runs.iter().collect();
+            newest.sort_by_key(|run| std::cmp::Reverse(run.created_at));
+            let pruned: Vec<String> = newest
+                .into_iter()
+                .skip(keep)
+                .filter(|run| index.promoted.as_deref() != Some(run.name.as_str()) && (!keep_tagged || run.tags.is_empty()))
+                .map(|run| run.name.clone())
+                .collect();
+            for name in &pruned {
+                let dir = runs.join(name);
+                if dir.exists() {
+                    fs::remove_dir_all(&dir).with_context(|| format!("cannot remove {}", dir.display()))?;
+                }
+            }
+            index.runs.retain(|run| !pruned.contains(&run.name));
+            Ok(pruned)
+        })
+    }
+
+    //Apply `change` to the index saved on disk, not to the one read when the registry was opened,
+    //so that trainings running at the same time do not overwrite each other's runs.
+    fn update<T>(&mut self, change: impl FnOnce(&mut Index) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        fs::create_dir_all(&self.root)?;
+        let path = self.root.join(LOCK);
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("cannot open {}", path.display()))?;
+        lock.lock().with_context(|| format!("cannot lock {}", path.display()))?;
+        self.index = Index::load(&self.root)?;
+        let changed = change(&mut self.index)?;
+        self.save()?;
+        Ok(changed)
+    }
+
+    //Written aside then renamed, so a crash never leaves a truncated index.
+    fn save(&self) -> anyhow::Result<()> {
+        let path = self.root.join(INDEX);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(&self.index)?)?;
+        fs::rename(&tmp, &path).with_context(|| format!("cannot write {}", path.display()))
+    }
+}
+
+/// `YYYYMMDD-HHMMSS` of Unix time `secs`, in UTC.
+pub fn utc(secs: i64) -> String {
+    let (days, time) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    //Civil date of a day count, from Howard Hinnant's date algorithms.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}{month:02}{day:02}-{:02}{:02}{:02}", time / 3600, time % 3600 / 60, time % 60)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{utc, Registry, Run};
+
+    fn run(name: &str, created_at: i64, tags: &[&str]) -> Run {
+        Run {
+            name: name.to_string(),
+            created_at,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            metrics: Default::default(),
+            data: None,
+            base: None,
+        }
+    }
+
+    #[test]
+    fn formats_utc() {
+        assert_eq!(utc(0), "19700101-000000");
+        assert_eq!(utc(951_782_400 + 3661), "20000229-010101");
+        assert_eq!(utc(1_792_368_000), "20261019-000000");
+    }
+
+    #[test]
+    fn resolves_promotes_and_prunes() {
+        let root = std::env::temp_dir().join(format!("ebsentinel-registry-{}", std::process::id()));
+        let mut registry = Registry::open(&root).unwrap();
+        for run in [run("a", 1, &["baseline"]), run("b", 2, &[]), run("c", 3, &[]), run("d", 4, &[])] {
+            fs::create_dir_all(registry.dir(&run.name)).unwrap();
+            registry.add(run).unwrap();
+        }
+        let (created, dir) = registry.create_run(vec![]).unwrap();
+        assert!(dir.is_dir() && registry.resolve(&created.name).is_err());
+        //Runs created in the same second never share a directory.
+        let (other, _) = registry.create_run(vec![]).unwrap();
+        assert_ne!(created.name, other.name);
+        registry.discard(&other).unwrap();
+        registry.discard(&created).unwrap();
+        assert!(!dir.exists());
+        assert_eq!(registry.resolve("latest").unwrap().name, "d");
+        assert_eq!(registry.resolve("baseline").unwrap().name, "a");
+        assert!(registry.resolve("nope").is_err());
+        assert!(registry.add(run("a", 5, &[])).is_err());
+
+        //A run added by another process is kept when this one changes the index.
+        Registry::open(&root).unwrap().add(run("e", 0, &[])).unwrap();
+        registry.set_metrics("a", [("f1".to_string(), 0.5)].into()).unwrap();
+        assert_eq!(registry.runs().len(), 5);
+        registry.prune(4, false).unwrap();
+
+        assert_eq!(registry.model_dir(None).unwrap(), registry.dir("d"));
+        registry.promote("b").unwrap();
+        assert_eq!(registry.model_dir(None).unwrap(), registry.dir("b"));
+
+        //The promoted and the tagged runs survive.
+        assert_eq!(registry.prune(1, true).unwrap(), vec!["c".to_string()]);
+        let registry = Registry::open(&root).unwrap();
+        let names: Vec<&str> = registry.runs().iter().map(|run| run.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "d"]);
+        assert!(!registry.dir("c").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
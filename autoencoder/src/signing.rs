@@ -0,0 +1,153 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+const ALGORITHM: &str = "ed25519";
+
+/// Short fingerprint of a public key: the first 8 bytes of its SHA-256, in hex.
+pub fn key_id(key: &VerifyingKey) -> String {
+    hex::encode(&Sha256::digest(key.as_bytes())[..8])
+}
+
+/// Write the secret key to `path`, readable by its owner only, and the public key to `path.pub`.
+/// Existing keys are never overwritten.
+pub fn write_keypair(key: &SigningKey, path: &Path) -> anyhow::Result<PathBuf> {
+    let public = public_path(path);
+    if public.exists() {
+        bail!("{} already exists", public.display());
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("cannot create {}", path.display()))?;
+    writeln!(file, "{}", hex::encode(key.to_bytes()))?;
+    fs::write(&public, format!("{}\n", hex::encode(key.verifying_key().as_bytes())))?;
+    Ok(public)
+}
+
+fn public_path(path: &Path) -> PathBuf {
+    let mut public = path.as_os_str().to_owned();
+    public.push(".pub");
+    public.into()
+}
+
+pub fn load_signing_key(path: &Path) -> anyhow::Result<SigningKey> {
+    Ok(SigningKey::from_bytes(&read_key(path)?))
+}
+
+pub fn load_verifying_key(path: &Path) -> anyhow::Result<VerifyingKey> {
+    VerifyingKey::from_bytes(&read_key(path)?).with_context(|| format!("invalid public key {}", path.display()))
+}
+
+//Keys are stored as 64 hex digits.
+fn read_key(path: &Path) -> anyhow::Result<[u8; 32]> {
+    let text = fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let mut key = [0; 32];
+    hex::decode_to_slice(text.trim(), &mut key).with_context(|| format!("{} is not an ed25519 key", path.display()))?;
+    Ok(key)
+}
+
+/// Signature of a model bundle, saved next to it as `<bundle>.sig`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Signature {
+    pub algorithm: String,
+    /// [`key_id`] of the signing key.
+    pub key_id: String,
+    pub signature: String,
+}
+
+impl Signature {
+    pub fn path(bundle: &Path) -> PathBuf {
+        let mut path = bundle.as_os_str().to_owned();
+        path.push(".sig");
+        path.into()
+    }
+
+    pub fn sign(bytes: &[u8], key: &SigningKey) -> Self {
+        Self {
+            algorithm: ALGORITHM.to_string(),
+            key_id: key_id(&key.verifying_key()),
+            signature: hex::encode(key.sign(bytes).to_bytes()),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        fs::write(path, serde_json::to_string_pretty(self)?).with_context(|| format!("cannot write {}", path.display()))
+    }
+
+    /// None if there is no signature at `path`.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Option<Self>> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(json) => Ok(Some(serde_json::from_str(&json).with_context(|| format!("invalid signature {}", path.display()))?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("cannot read {}", path.display())),
+        }
+    }
+
+    /// Index of the trusted key that made this signature of `bytes`. Fails if the signature was made
+    /// by another key, or if `bytes` changed since they were signed.
+    pub fn verify(&self, bytes: &[u8], trusted: &[VerifyingKey]) -> anyhow::Result<usize> {
+        if self.algorithm != ALGORITHM {
+            bail!("unsupported signature algorithm {}", self.algorithm);
+        }
+        let Some(index) = trusted.iter().position(|key| key_id(key) == self.key_id) else {
+            bail!("signed by key {}, which is not trusted", self.key_id);
+        };
+        let mut signature = [0; 64];
+        hex::decode_to_slice(&self.signature, &mut signature).context("malformed signature")?;
+        trusted[index]
+            .verify(bytes, &ed25519_dalek::Signature::from_bytes(&signature))
+            .map_err(|_| anyhow::anyhow!("the signature of key {} does not match, the model was modified after signing", self.key_id))?;
+        Ok(index)
+    }
+}
+
+/// Sign the bundle at `path` with `key`.
+pub fn sign_bundle(path: &Path, key: &SigningKey) -> anyhow::Result<Signature> {
+    let bytes = fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let signature = Signature::sign(&bytes, key);
+    signature.save(Signature::path(path))?;
+    Ok(signature)
+}
+
+/// Index of the trusted key that signed `bytes`, read from the bundle at `path`.
+pub fn verify_bundle(path: &Path, bytes: &[u8], trusted: &[VerifyingKey]) -> anyhow::Result<usize> {
+    match Signature::load(Signature::path(path))? {
+        Some(signature) => signature.verify(bytes, trusted),
+        None => bail!("{} is not signed", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ed25519_dalek::SigningKey;
+
+    use super::{key_id, Signature};
+
+    #[test]
+    fn detects_tampering_and_unknown_keys() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let trusted = [other.verifying_key(), key.verifying_key()];
+        let signature = Signature::sign(b"bundle", &key);
+        assert_eq!(signature.key_id, key_id(&key.verifying_key()));
+        assert_eq!(signature.verify(b"bundle", &trusted).unwrap(), 1);
+        assert!(signature.verify(b"bundlf", &trusted).is_err());
+        assert!(signature.verify(b"bundle", &trusted[..1]).is_err());
+        //A signature made by another key under the trusted key's id.
+        let forged = Signature { key_id: signature.key_id.clone(), ..Signature::sign(b"bundle", &other) };
+        assert!(forged.verify(b"bundle", &trusted).is_err());
+    }
+}
@@ -1,3 +1,7 @@
 #![no_std]
 
+pub mod syscalls;
+
 pub const MAX_SYSCALLS: u32 = 512;
+/// Maximum number of processes a single sensor can monitor at once.
+pub const MAX_TARGETS: u32 = 64;
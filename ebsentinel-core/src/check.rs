@@ -0,0 +1,185 @@
+use std::{ffi::CStr, fs, path::Path};
+
+use aya::Btf;
+use ebsentinel_common::{MAX_SYSCALLS, MAX_TARGETS};
+
+use crate::sensor::{Sensor, DEFAULT_PIN_PATH};
+
+const CAP_SYS_ADMIN: u32 = 21;
+const CAP_PERFMON: u32 = 38;
+const CAP_BPF: u32 = 39;
+
+//BTF-enabled tracepoints were added in Linux 5.5.
+const MIN_KERNEL: (u32, u32) = (5, 5);
+//Since Linux 5.11 eBPF memory is charged to the cgroup instead of the locked memory limit.
+const MEMCG_KERNEL: (u32, u32) = (5, 11);
+
+/// Outcome of one environment check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: true,
+            detail: detail.into(),
+        }
+    }
+
+    pub fn fail(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            passed: false,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Check the kernel and privilege prerequisites of the sensor.
+///
+/// The sensor is loaded and attached for a moment when the privileges allow it.
+pub fn run_checks() -> Vec<Check> {
+    let privileges = privileges();
+    let attach = tp_btf(privileges.passed);
+    vec![kernel_version(), btf(), privileges, memlock(), bpffs(), attach]
+}
+
+//Release of the running kernel with its major and minor version.
+fn kernel() -> Option<(String, (u32, u32))> {
+    let mut uname: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uname) } != 0 {
+        return None;
+    }
+    let release = unsafe { CStr::from_ptr(uname.release.as_ptr()) }.to_string_lossy().into_owned();
+
+    let mut numbers = release.split(|c: char| !c.is_ascii_digit()).filter_map(|n| n.parse::<u32>().ok());
+    let version = (numbers.next().unwrap_or(0), numbers.next().unwrap_or(0));
+    Some((release, version))
+}
+
+fn kernel_version() -> Check {
+    let Some((release, version)) = kernel() else {
+        return Check::fail("kernel", "uname failed");
+    };
+    if version >= MIN_KERNEL {
+        Check::pass("kernel", release)
+    } else {
+        Check::fail(
+            "kernel",
+            format!("{release}, BTF tracepoints need Linux {}.{} or newer", MIN_KERNEL.0, MIN_KERNEL.1),
+        )
+    }
+}
+
+fn btf() -> Check {
+    match Btf::from_sys_fs() {
+        Ok(_) => Check::pass("btf", "/sys/kernel/btf/vmlinux is readable"),
+        Err(e) => Check::fail(
+            "btf",
+            format!("cannot read /sys/kernel/btf/vmlinux ({e}), the kernel needs CONFIG_DEBUG_INFO_BTF=y"),
+        ),
+    }
+}
+
+fn privileges() -> Check {
+    if unsafe { libc::geteuid() } == 0 {
+        return Check::pass("privileges", "running as root");
+    }
+
+    let cap_eff = fs::read_to_string("/proc/self/status").ok().and_then(|status| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .and_then(|caps| u64::from_str_radix(caps.trim(), 16).ok())
+    });
+    let Some(cap_eff) = cap_eff else {
+        return Check::fail("privileges", "cannot read the effective capabilities from /proc/self/status");
+    };
+
+    let has = |cap: u32| cap_eff & (1 << cap) != 0;
+    if has(CAP_BPF) && has(CAP_PERFMON) {
+        Check::pass("privileges", "CAP_BPF and CAP_PERFMON")
+    } else if has(CAP_SYS_ADMIN) {
+        Check::pass("privileges", "CAP_SYS_ADMIN")
+    } else {
+        Check::fail(
+            "privileges",
+            "not root and missing CAP_BPF and CAP_PERFMON, run with sudo or grant them with setcap",
+        )
+    }
+}
+
+fn memlock() -> Check {
+    let rlim = libc::rlimit {
+        rlim_cur: libc::RLIM_INFINITY,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    if unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &rlim) } == 0 {
+        return Check::pass("memlock", "locked memory limit removed");
+    }
+
+    let mut current: libc::rlimit = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut current) };
+    let limit = current.rlim_cur;
+    match kernel() {
+        Some((release, version)) if version >= MEMCG_KERNEL => Check::pass(
+            "memlock",
+            format!("cannot remove the locked memory limit ({limit} bytes), unused by Linux {release} which charges eBPF memory to the cgroup"),
+        ),
+        _ if limit == libc::RLIM_INFINITY || limit >= sensor_memory() => Check::pass(
+            "memlock",
+            format!("cannot remove the locked memory limit, its {limit} bytes fit the sensor maps"),
+        ),
+        _ => Check::fail(
+            "memlock",
+            format!(
+                "locked memory limit of {limit} bytes, kernels older than {}.{} need about {} bytes to create the sensor maps, raise it with `ulimit -l` or run as root",
+                MEMCG_KERNEL.0,
+                MEMCG_KERNEL.1,
+                sensor_memory()
+            ),
+        ),
+    }
+}
+
+//Locked memory the maps of a sensor take: the per-cpu counters, and a page per hash map and for the program.
+fn sensor_memory() -> u64 {
+    let cpus = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) }.max(1) as u64;
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(4096) as u64;
+    let counters = (MAX_TARGETS * MAX_SYSCALLS) as u64 * 8 * cpus;
+    counters.div_ceil(page) * page + 4 * page
+}
+
+fn bpffs() -> Check {
+    let mounted = fs::read_to_string("/proc/mounts")
+        .map(|mounts| mounts.lines().any(|line| line.split_whitespace().nth(2) == Some("bpf")))
+        .unwrap_or(false);
+    if mounted {
+        let pinned = if Path::new(DEFAULT_PIN_PATH).exists() {
+            format!(", a sensor is pinned in {DEFAULT_PIN_PATH}")
+        } else {
+            String::new()
+        };
+        Check::pass("bpffs", format!("bpf filesystem mounted{pinned}"))
+    } else {
+        Check::fail(
+            "bpffs",
+            "no bpf filesystem mounted, --pin needs `mount -t bpf bpf /sys/fs/bpf`",
+        )
+    }
+}
+
+fn tp_btf(privileged: bool) -> Check {
+    if !privileged {
+        return Check::fail("attach tp_btf", "not tried without the privileges to load eBPF programs");
+    }
+    match Sensor::load() {
+        Ok(_) => Check::pass("attach tp_btf", "sensor loaded and attached to sys_enter"),
+        Err(e) => Check::fail("attach tp_btf", format!("{e:#}")),
+    }
+}
@@ -0,0 +1,102 @@
+use std::{
+    ffi::CString,
+    io,
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+};
+
+use anyhow::{bail, Context};
+use tokio::task::{self, JoinHandle};
+
+//Exit status of the child when the command cannot be executed, like in shells.
+const EXEC_FAILED: i32 = 127;
+
+/// A command forked and stopped before its first instruction, so it can be monitored from its first syscall.
+pub struct Launched {
+    pid: libc::pid_t,
+    resumed: bool,
+}
+
+impl Launched {
+    /// Fork `command` and leave the child stopped before it executes the command.
+    pub fn spawn_stopped(command: &[String]) -> anyhow::Result<Self> {
+        if command.is_empty() {
+            bail!("no command to launch");
+        }
+        //Everything the child needs is allocated before the fork, it may only call async-signal-safe functions.
+        let args = command
+            .iter()
+            .map(|arg| CString::new(arg.as_str()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("command arguments cannot contain NUL bytes")?;
+        let mut argv: Vec<*const libc::c_char> = args.iter().map(|arg| arg.as_ptr()).collect();
+        argv.push(std::ptr::null());
+
+        let pid = unsafe { libc::fork() };
+        if pid < 0 {
+            return Err(io::Error::last_os_error()).context("fork failed");
+        }
+        if pid == 0 {
+            unsafe {
+                libc::raise(libc::SIGSTOP);
+                libc::execvp(argv[0], argv.as_ptr());
+                libc::_exit(EXEC_FAILED);
+            }
+        }
+
+        let launched = Self { pid, resumed: false };
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) } < 0 {
+            return Err(io::Error::last_os_error()).context("waiting for the launched command to stop failed");
+        }
+        if !libc::WIFSTOPPED(status) {
+            bail!("launched command exited before it could be monitored");
+        }
+        Ok(launched)
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid as u32
+    }
+
+    /// Let the command run and wait for its exit in the background.
+    pub fn resume(mut self) -> anyhow::Result<JoinHandle<io::Result<ExitStatus>>> {
+        if unsafe { libc::kill(self.pid, libc::SIGCONT) } != 0 {
+            return Err(io::Error::last_os_error()).context("resuming the launched command failed");
+        }
+        self.resumed = true;
+        let pid = self.pid;
+        Ok(task::spawn_blocking(move || {
+            let mut status = 0;
+            loop {
+                if unsafe { libc::waitpid(pid, &mut status, 0) } >= 0 {
+                    return Ok(ExitStatus::from_raw(status));
+                }
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    return Err(error);
+                }
+            }
+        }))
+    }
+}
+
+impl Drop for Launched {
+    //A command that was never resumed is killed, e.g. when the sensor failed to load.
+    fn drop(&mut self) {
+        if !self.resumed {
+            unsafe {
+                libc::kill(self.pid, libc::SIGKILL);
+                libc::waitpid(self.pid, std::ptr::null_mut(), 0);
+            }
+        }
+    }
+}
+
+/// Exit code of a shell running the command: its own, or 128 plus the signal that killed it.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
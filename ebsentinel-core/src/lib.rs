@@ -1,11 +1,21 @@
-use std::time::Duration;
+use std::{path::Path, time::Duration};
 
 #[rustfmt::skip]
 use proc_mon::ProcMon;
+use sensor::Sensor;
+pub use ebsentinel_features::process_data;
+pub mod check;
+pub mod launch;
 pub mod proc_mon;
-pub mod process_data;
-pub fn run_ebsentinel_ebpf(pid: u32)-> anyhow::Result<ProcMon>{
+pub mod sample_source;
+pub mod sensor;
+pub mod synthetic;
+/// Monitor `pid` with a sensor of its own, or with the one pinned under `pin` if given.
+pub fn run_ebsentinel_ebpf(pid: u32, pin: Option<&Path>)-> anyhow::Result<ProcMon>{
     env_logger::init();
-    let proc_mon= ProcMon::new(pid, Duration::from_millis(100));
-    Ok(proc_mon)
-}
\ No newline at end of file
+    let sensor = match pin {
+        Some(path) => Sensor::open_or_load_pinned(path)?,
+        None => Sensor::load()?,
+    };
+    sensor.monitor(pid, Duration::from_millis(100))
+}
@@ -1,118 +1,181 @@
-use std::time::Duration;
-
-use aya::{
-    maps::{PerCpuArray, PerCpuValues},
-    programs::BtfTracePoint,
-    util::nr_cpus,
-    Btf, Ebpf,
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
+
 use ebsentinel_common::MAX_SYSCALLS;
-use log::{debug, warn};
+use log::warn;
 use tokio::{
-    sync::mpsc::{unbounded_channel, UnboundedReceiver},
+    sync::{
+        mpsc::{channel, error::TrySendError, Receiver},
+        Notify,
+    },
     time::sleep,
 };
 
-use crate::process_data::{DataProcessor, Differentiator, Normalizer};
+use crate::{
+    process_data::{DataProcessor, Differentiator, Normalizer},
+    sample_source::{now_ns, Sample, SampleSource, CHANNEL_CAPACITY},
+    sensor::Sensor,
+};
+
+/// Handle on a process monitored by a [`Sensor`], created with [`Sensor::monitor`].
+///
+/// The process stops being monitored when its last handle, in any process sharing the sensor, is
+/// dropped.
 pub struct ProcMon {
     monitored_pid: u32,
     polling_rate: Duration,
-    ebpf: Ebpf,
+    slot: u32,
+    sensor: Sensor,
+    dropped: Arc<AtomicU64>,
+    finish: Arc<Notify>,
 }
 
 impl ProcMon {
-    pub fn new(monitored_pid: u32, polling_rate: Duration) -> Self {
-        let ebpf = Self::load_epbf().unwrap();
+    pub(crate) fn new(
+        sensor: Sensor,
+        monitored_pid: u32,
+        slot: u32,
+        polling_rate: Duration,
+    ) -> Self {
         Self {
             monitored_pid,
             polling_rate,
-            ebpf,
+            slot,
+            sensor,
+            dropped: Arc::new(AtomicU64::new(0)),
+            finish: Arc::new(Notify::new()),
         }
     }
 
-    pub fn run(&mut self) -> anyhow::Result<UnboundedReceiver<Vec<f32>>> {
-        let (tx, rx) = unbounded_channel();
-        let mut monitored_pid: PerCpuArray<&mut aya::maps::MapData, u32> =
-            PerCpuArray::try_from(self.ebpf.map_mut("MONITORED_PID").unwrap())?;
+    pub fn pid(&self) -> u32 {
+        self.monitored_pid
+    }
 
-        let nr_cpus = nr_cpus().map_err(|(_, error)| error)?;
+    pub fn polling_rate(&self) -> Duration {
+        self.polling_rate
+    }
 
-        monitored_pid
-            .set(
-                0,
-                PerCpuValues::try_from(vec![self.monitored_pid; nr_cpus])?,
-                0,
-            )
-            .unwrap();
+    pub fn sensor(&self) -> &Sensor {
+        &self.sensor
+    }
+
+    /// Number of samples dropped because the consumer did not keep up.
+    pub fn drop_counter(&self) -> Arc<AtomicU64> {
+        self.dropped.clone()
+    }
 
-        let map = self.ebpf.take_map("SYSCALLS_COUNTERS").unwrap();
-        let syscall_map: PerCpuArray<aya::maps::MapData, u64> = PerCpuArray::try_from(map)?;
+    /// Read the counters one last time, e.g. once the monitored process exited, and stop sampling.
+    /// The receiver of [`ProcMon::run`] yields that last sample, then ends.
+    pub fn finish(&self) {
+        self.finish.notify_one();
+    }
+
+    pub fn run(&mut self) -> anyhow::Result<Receiver<Sample>> {
+        let (tx, rx) = channel(CHANNEL_CAPACITY);
+        let dropped = self.dropped.clone();
+        let finish = self.finish.clone();
 
-        let polling_rate = self.polling_rate.clone();
+        let polling_rate = self.polling_rate;
+        let slot = self.slot;
+        let sensor = self.sensor.clone();
 
         let mut differentiator = Differentiator::new(&self.polling_rate);
+        let mut syscall_counts = vec![0; MAX_SYSCALLS as usize];
+        //Start from the current value of the counters, the pid may already be monitored by another handle.
+        sensor.read_counts(slot, &mut syscall_counts);
+        differentiator.prime(&syscall_counts);
 
         tokio::spawn(async move {
-            let mut prev = Vec::new();
-            let mut syscall_counts = vec![0; MAX_SYSCALLS as usize];
+            let mut prev = syscall_counts.clone();
+            let mut last = false;
             loop {
-                for i in 0..MAX_SYSCALLS as usize {
-                    //Aggregate Syscalls counts from all cpus
-                    syscall_counts[i] = 0;
-                    if let Ok(counts) = syscall_map.get(&(i as u32), 0) {
-                        for cpu_val in counts.iter() {
-                            syscall_counts[i] += cpu_val;
-                        }
-                    }
-                }
+                //Aggregate Syscalls counts from all cpus
+                sensor.read_counts(slot, &mut syscall_counts);
+                let timestamp_ns = now_ns();
 
                 if syscall_counts != prev {
                     //Compute derivative
                     let rates = differentiator.process(&syscall_counts);
                     if let Ok(rates) = rates {
-                        let norm = Normalizer.process(&rates).unwrap();
-                        tx.send(norm).unwrap();
+                        let features = Normalizer.process(&rates).unwrap();
+                        let sample = Sample {
+                            timestamp_ns,
+                            counts: syscall_counts.clone(),
+                            rates,
+                            features,
+                        };
+                        //The consumer waits for the last sample, it is not dropped.
+                        let sent = if last {
+                            tx.send(sample).await.map_err(|e| TrySendError::Closed(e.0))
+                        } else {
+                            tx.try_send(sample)
+                        };
+                        match sent {
+                            Ok(()) => {}
+                            Err(TrySendError::Full(_)) => {
+                                if dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+                                    warn!("consumer is too slow, dropping samples");
+                                }
+                            }
+                            Err(TrySendError::Closed(_)) => break,
+                        }
                     }
                     prev = syscall_counts.clone();
                 }
 
-                sleep(polling_rate).await
+                if last {
+                    break;
+                }
+                last = tokio::select! {
+                    _ = sleep(polling_rate) => false,
+                    _ = finish.notified() => true,
+                };
             }
         });
 
         Ok(rx)
     }
+}
 
-    //Load ebsentinel-ebpf program to kernel vm.
-    pub fn load_epbf() -> anyhow::Result<Ebpf> {
-        // Bump the memlock rlimit. This is needed for older kernels that don't use the
-        // new memcg based accounting, see https://lwn.net/Articles/837122/
-        let rlim = libc::rlimit {
-            rlim_cur: libc::RLIM_INFINITY,
-            rlim_max: libc::RLIM_INFINITY,
-        };
-        let ret = unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &rlim) };
-        if ret != 0 {
-            debug!("remove limit on locked memory failed, ret is: {}", ret);
-        }
+impl SampleSource for ProcMon {
+    fn run(&mut self) -> anyhow::Result<Receiver<Sample>> {
+        ProcMon::run(self)
+    }
+}
 
-        //include ebsentinel-ebpf object file as raw bytes
-        let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
-            env!("OUT_DIR"),
-            "/ebsentinel"
-        )))?;
+impl Drop for ProcMon {
+    fn drop(&mut self) {
+        self.sensor.unregister(self.monitored_pid, self.slot);
+    }
+}
 
-        if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
-            // This can happen if you remove all log statements from your eBPF program.
-            warn!("failed to initialize eBPF logger: {}", e);
-        }
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
 
-        let btf = Btf::from_sys_fs()?;
+    use crate::{launch::Launched, sensor::Sensor};
 
-        let program: &mut BtfTracePoint = ebpf.program_mut("ebsentinel").unwrap().try_into()?;
-        program.load("sys_enter", &btf)?;
-        program.attach()?;
+    #[tokio::test]
+    #[ignore = "loads the eBPF program, needs CAP_BPF"]
+    async fn records_commands_exiting_before_the_first_poll() {
+        let launched = Launched::spawn_stopped(&["true".to_string()]).unwrap();
+        let mut proc_mon = Sensor::load()
+            .unwrap()
+            .monitor(launched.pid(), Duration::from_millis(100))
+            .unwrap();
+        let mut rx = proc_mon.run().unwrap();
+        launched.resume().unwrap().await.unwrap().unwrap();
 
-        Ok(ebpf)
+        proc_mon.finish();
+        let mut samples = 0;
+        while rx.recv().await.is_some() {
+            samples += 1;
+        }
+        assert!(samples >= 1);
     }
 }
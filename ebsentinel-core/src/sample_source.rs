@@ -0,0 +1,32 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::mpsc::Receiver;
+
+/// Capacity of the channel between a source and its consumer.
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// One polling interval of a monitored process.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// Unix time in nanoseconds at which the counters were read.
+    pub timestamp_ns: i64,
+    /// Cumulative syscall counters, indexed by syscall number.
+    pub counts: Vec<u64>,
+    /// Syscalls per second, indexed by syscall number.
+    pub rates: Vec<f32>,
+    /// `rates` normalized, the input of the model.
+    pub features: Vec<f32>,
+}
+
+/// Anything that produces syscall samples.
+///
+/// [`crate::proc_mon::ProcMon`] reads them from the kernel, while
+/// [`crate::synthetic::SyntheticSource`] generates them without any eBPF program.
+pub trait SampleSource {
+    fn run(&mut self) -> anyhow::Result<Receiver<Sample>>;
+}
+
+/// Current Unix time in nanoseconds.
+pub fn now_ns() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as i64)
+}
@@ -0,0 +1,497 @@
+use std::{
+    collections::{HashMap as StdHashMap, HashSet},
+    fs::{self, File},
+    io,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context};
+use aya::{
+    maps::{HashMap, Map, MapData, MapError, PerCpuArray, PerCpuValues},
+    programs::{links::FdLink, loaded_programs, BtfTracePoint},
+    sys::SyscallError,
+    util::nr_cpus,
+    Btf, Ebpf,
+};
+use ebsentinel_common::{MAX_SYSCALLS, MAX_TARGETS};
+use log::{debug, warn};
+
+use crate::proc_mon::ProcMon;
+
+/// Where a shared sensor is pinned by default.
+pub const DEFAULT_PIN_PATH: &str = "/sys/fs/bpf/ebsentinel";
+
+const COUNTERS_MAP: &str = "SYSCALLS_COUNTERS";
+const TARGETS_MAP: &str = "MONITORED_PIDS";
+const MONITORS_MAP: &str = "MONITORS";
+const OWNERS_MAP: &str = "SLOT_OWNERS";
+const MAPS: [&str; 4] = [COUNTERS_MAP, TARGETS_MAP, MONITORS_MAP, OWNERS_MAP];
+const PROGRAM: &str = "ebsentinel";
+const LINK: &str = "link";
+
+/// The eBPF program attached to `sys_enter` and its maps.
+///
+/// A single program counts the syscalls of every monitored process, so the per-syscall overhead
+/// does not depend on the number of targets. Cloning a `Sensor` is cheap and the program stays
+/// attached until the last clone, including the ones held by [`ProcMon`]s, is dropped.
+///
+/// A sensor can also be pinned to bpffs, so that several processes read the same counters and
+/// the program stays attached after they exit, until the pin directory is removed with
+/// [`Sensor::unpin`].
+#[derive(Clone)]
+pub struct Sensor {
+    inner: Arc<Inner>,
+}
+
+/// Activity of the sensor programs loaded in the kernel.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProgramStats {
+    pub programs: usize,
+    /// Only counted while the `kernel.bpf_stats_enabled` sysctl is set.
+    pub run_count: u64,
+    pub run_time: Duration,
+}
+
+struct Inner {
+    counters: RwLock<PerCpuArray<MapData, u64>>,
+    targets: Mutex<Targets>,
+    //Pid and start time of this process, identifying its monitors among the ones of the processes sharing the sensor.
+    agent: (u32, u64),
+    //Pin directory, locked while registering pids so that processes sharing the sensor do not race.
+    pin_dir: Option<File>,
+    //None when the program was loaded by another process.
+    _ebpf: Option<Ebpf>,
+}
+
+struct Targets {
+    pids: HashMap<MapData, u32, u32>,
+    monitors: HashMap<MapData, u64, u64>,
+    owners: HashMap<MapData, u32, u32>,
+    //Slot and number of handles of every pid this process monitors.
+    local: StdHashMap<u32, (u32, u32)>,
+}
+
+impl Sensor {
+    /// Load the program into the kernel and attach it.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut ebpf = load_ebpf()?;
+        program(&mut ebpf)?
+            .attach()
+            .context("attach sys_enter program")?;
+        let maps = take_maps(&mut ebpf)?;
+        Self::new(maps, None, Some(ebpf))
+    }
+
+    /// Load the program, attach it and pin it with its maps under `path`.
+    pub fn load_pinned<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let dir = create_dir(path)?;
+        let (maps, ebpf) = {
+            let _lock = PinLock::new(&dir).context("lock pinned sensor")?;
+            pin(path)?
+        };
+        Self::new(maps, Some(dir), Some(ebpf))
+    }
+
+    /// Use a sensor pinned under `path` by another process.
+    pub fn open_pinned<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let dir = open_dir(path)?;
+        let maps = {
+            let _lock = PinLock::new(&dir).context("lock pinned sensor")?;
+            open_pins(path)?
+        };
+        Self::new(maps, Some(dir), None)
+    }
+
+    /// Use the sensor pinned under `path`, loading and pinning it first if there is none.
+    pub fn open_or_load_pinned<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let dir = create_dir(path)?;
+        //Locked so that processes starting together load the sensor once, and only open it once pinned.
+        let (maps, ebpf) = {
+            let _lock = PinLock::new(&dir).context("lock pinned sensor")?;
+            if path.join(LINK).exists() {
+                (open_pins(path)?, None)
+            } else {
+                let (maps, ebpf) = pin(path)?;
+                (maps, Some(ebpf))
+            }
+        };
+        Self::new(maps, Some(dir), ebpf)
+    }
+
+    /// Remove the pins under `path`, the program is detached once no process uses it anymore.
+    pub fn unpin<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
+        fs::remove_dir_all(path)
+    }
+
+    /// Sum the statistics of every loaded sensor program, pinned or not.
+    pub fn program_stats() -> anyhow::Result<ProgramStats> {
+        let mut stats = ProgramStats::default();
+        for info in loaded_programs() {
+            let info = info?;
+            if info.name_as_str() == Some(PROGRAM) {
+                stats.programs += 1;
+                stats.run_count += info.run_count();
+                stats.run_time += info.run_time();
+            }
+        }
+        Ok(stats)
+    }
+
+    fn new(maps: [Map; 4], pin_dir: Option<File>, ebpf: Option<Ebpf>) -> anyhow::Result<Self> {
+        let [counters, pids, monitors, owners] = maps;
+        let agent = std::process::id();
+        Ok(Self {
+            inner: Arc::new(Inner {
+                counters: RwLock::new(PerCpuArray::try_from(counters)?),
+                targets: Mutex::new(Targets {
+                    pids: HashMap::try_from(pids)?,
+                    monitors: HashMap::try_from(monitors)?,
+                    owners: HashMap::try_from(owners)?,
+                    local: StdHashMap::new(),
+                }),
+                agent: (agent, start_time(agent).context("read own start time")?),
+                pin_dir,
+                _ebpf: ebpf,
+            }),
+        })
+    }
+
+    /// Start counting the syscalls of `pid`.
+    ///
+    /// If `pid` is already monitored, possibly by another process sharing a pinned sensor, its
+    /// counters are shared and it stays monitored until the last of its monitors is dropped.
+    pub fn monitor(&self, pid: u32, polling_rate: Duration) -> anyhow::Result<ProcMon> {
+        let slot = self.register(pid)?;
+        Ok(ProcMon::new(self.clone(), pid, slot, polling_rate))
+    }
+
+    fn register(&self, pid: u32) -> anyhow::Result<u32> {
+        let mut targets = self.inner.targets.lock().unwrap();
+        if let Some((slot, handles)) = targets.local.get_mut(&pid) {
+            *handles += 1;
+            return Ok(*slot);
+        }
+
+        let _lock = self.lock_pin_dir()?;
+        targets.reclaim();
+        let (agent, started) = self.inner.agent;
+        let key = monitor_key(agent, pid);
+        if let Ok(slot) = targets.pids.get(&pid, 0) {
+            targets
+                .monitors
+                .insert(key, started, 0)
+                .context("register monitored pid")?;
+            targets.local.insert(pid, (slot, 1));
+            return Ok(slot);
+        }
+
+        let slot = claim_slot(&mut targets.owners, pid)?;
+        let registered = self.reset_counts(slot).and_then(|()| {
+            targets.monitors.insert(key, started, 0)?;
+            targets.pids.insert(pid, slot, 0)?;
+            Ok(())
+        });
+        if let Err(e) = registered {
+            let _ = targets.monitors.remove(&key);
+            let _ = targets.owners.remove(&slot);
+            return Err(e.context("register monitored pid"));
+        }
+        targets.local.insert(pid, (slot, 1));
+        Ok(slot)
+    }
+
+    /// Drop a monitor of `pid`, the last one of all the processes sharing the sensor frees its slot.
+    pub(crate) fn unregister(&self, pid: u32, slot: u32) {
+        let mut targets = self.inner.targets.lock().unwrap();
+        match targets.local.get_mut(&pid) {
+            Some((_, handles)) if *handles > 1 => {
+                *handles -= 1;
+                return;
+            }
+            _ => targets.local.remove(&pid),
+        };
+        let unregistered = self.lock_pin_dir().and_then(|_lock| {
+            targets
+                .monitors
+                .remove(&monitor_key(self.inner.agent.0, pid))?;
+            let monitored = targets
+                .monitors
+                .keys()
+                .filter_map(Result::ok)
+                .any(|key| key as u32 == pid);
+            if !monitored {
+                //Stop counting before the slot can be handed to another pid.
+                targets.pids.remove(&pid)?;
+                targets.owners.remove(&slot)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = unregistered {
+            warn!("failed to unregister pid {pid}: {e}");
+        }
+    }
+
+    //Lock the pin directory until the guard is dropped, processes sharing a sensor do not share its mutex.
+    fn lock_pin_dir(&self) -> anyhow::Result<Option<PinLock<'_>>> {
+        self.inner
+            .pin_dir
+            .as_ref()
+            .map(PinLock::new)
+            .transpose()
+            .context("lock pinned sensor")
+    }
+
+    //Zero the counters of `slot` so that a new pid does not inherit the ones of the previous owner.
+    fn reset_counts(&self, slot: u32) -> anyhow::Result<()> {
+        let cpus = nr_cpus().map_err(|(_, e)| e).context("count cpus")?;
+        let mut counters = self.inner.counters.write().unwrap();
+        for i in 0..MAX_SYSCALLS {
+            counters.set(
+                slot * MAX_SYSCALLS + i,
+                PerCpuValues::try_from(vec![0; cpus])?,
+                0,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Read the counters of `slot`, aggregated over all cpus.
+    pub(crate) fn read_counts(&self, slot: u32, syscall_counts: &mut [u64]) {
+        let counters = self.inner.counters.read().unwrap();
+        for (i, count) in syscall_counts.iter_mut().enumerate() {
+            *count = 0;
+            if let Ok(counts) = counters.get(&(slot * MAX_SYSCALLS + i as u32), 0) {
+                for cpu_val in counts.iter() {
+                    *count += cpu_val;
+                }
+            }
+        }
+    }
+}
+
+impl Targets {
+    //Drop the monitors of processes that exited without dropping them, e.g. killed ones, then the
+    //pids and slots no monitor is left for. Called with the pin directory locked.
+    fn reclaim(&mut self) {
+        let dead: Vec<u64> = self
+            .monitors
+            .iter()
+            .filter_map(Result::ok)
+            .filter(|(key, started)| start_time((key >> 32) as u32).ok() != Some(*started))
+            .map(|(key, _)| key)
+            .collect();
+        for key in dead {
+            debug!(
+                "reclaim monitor of pid {} left by exited process {}",
+                key as u32,
+                key >> 32
+            );
+            let _ = self.monitors.remove(&key);
+        }
+
+        let monitored: HashSet<u32> = self
+            .monitors
+            .keys()
+            .filter_map(Result::ok)
+            .map(|key| key as u32)
+            .collect();
+        let orphans: Vec<u32> = self
+            .pids
+            .keys()
+            .filter_map(Result::ok)
+            .filter(|pid| !monitored.contains(pid))
+            .collect();
+        for pid in orphans {
+            let _ = self.pids.remove(&pid);
+        }
+        //Slots of unmonitored pids, and slots claimed by a process killed before it registered the pid.
+        let slots: StdHashMap<u32, u32> = self
+            .pids
+            .iter()
+            .filter_map(Result::ok)
+            .map(|(pid, slot)| (slot, pid))
+            .collect();
+        let unused: Vec<u32> = self
+            .owners
+            .iter()
+            .filter_map(Result::ok)
+            .filter(|(slot, pid)| slots.get(slot) != Some(pid))
+            .map(|(slot, _)| slot)
+            .collect();
+        for slot in unused {
+            let _ = self.owners.remove(&slot);
+        }
+    }
+}
+
+//A monitor of `pid` by the process `agent`.
+fn monitor_key(agent: u32, pid: u32) -> u64 {
+    (agent as u64) << 32 | pid as u64
+}
+
+//Start time of a process in clock ticks since boot, telling it apart from a later one reusing its pid.
+fn start_time(pid: u32) -> io::Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    //The command name may contain spaces, the fields after it are separated by single spaces.
+    stat.rsplit_once(')')
+        .and_then(|(_, fields)| fields.split(' ').nth(20))
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed /proc/{pid}/stat"),
+            )
+        })
+}
+
+//Load ebsentinel-ebpf program to kernel vm.
+fn load_ebpf() -> anyhow::Result<Ebpf> {
+    // Bump the memlock rlimit. This is needed for older kernels that don't use the
+    // new memcg based accounting, see https://lwn.net/Articles/837122/
+    let rlim = libc::rlimit {
+        rlim_cur: libc::RLIM_INFINITY,
+        rlim_max: libc::RLIM_INFINITY,
+    };
+    let ret = unsafe { libc::setrlimit(libc::RLIMIT_MEMLOCK, &rlim) };
+    if ret != 0 {
+        debug!("remove limit on locked memory failed, ret is: {}", ret);
+    }
+
+    //include ebsentinel-ebpf object file as raw bytes
+    let mut ebpf = aya::Ebpf::load(aya::include_bytes_aligned!(concat!(
+        env!("OUT_DIR"),
+        "/ebsentinel"
+    )))
+    .context("load eBPF object")?;
+
+    if let Err(e) = aya_log::EbpfLogger::init(&mut ebpf) {
+        // This can happen if you remove all log statements from your eBPF program.
+        warn!("failed to initialize eBPF logger: {}", e);
+    }
+
+    let btf = Btf::from_sys_fs().context("read kernel BTF from /sys/kernel/btf/vmlinux")?;
+
+    program(&mut ebpf)?
+        .load("sys_enter", &btf)
+        .context("load sys_enter program")?;
+
+    Ok(ebpf)
+}
+
+fn program(ebpf: &mut Ebpf) -> anyhow::Result<&mut BtfTracePoint> {
+    Ok(ebpf
+        .program_mut(PROGRAM)
+        .ok_or_else(|| anyhow!("{PROGRAM} program not found"))?
+        .try_into()?)
+}
+
+fn take_maps(ebpf: &mut Ebpf) -> anyhow::Result<[Map; 4]> {
+    let [counters, pids, refs, owners] = MAPS.map(|name| {
+        ebpf.take_map(name)
+            .ok_or_else(|| anyhow!("{name} map not found"))
+    });
+    Ok([counters?, pids?, refs?, owners?])
+}
+
+//Load and attach the program, then pin its maps and, last, its link, so that a pinned link means
+//a complete sensor. The pins are removed if one fails, which detaches the program.
+fn pin(path: &Path) -> anyhow::Result<([Map; 4], Ebpf)> {
+    //Leftovers of a process killed while pinning, no complete sensor uses them.
+    for name in MAPS.iter().chain(&[PROGRAM]) {
+        match fs::remove_file(path.join(name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("remove stale {name} pin"))
+            }
+            _ => {}
+        }
+    }
+
+    let mut ebpf = load_ebpf()?;
+    let link_id = program(&mut ebpf)?
+        .attach()
+        .context("attach sys_enter program")?;
+    let maps = take_maps(&mut ebpf)?;
+    let mut pinned: Vec<PathBuf> = Vec::new();
+    let mut pin_all = || -> anyhow::Result<()> {
+        for (map, name) in maps.iter().zip(MAPS) {
+            map.pin(path.join(name))
+                .with_context(|| format!("pin {name} map"))?;
+            pinned.push(path.join(name));
+        }
+        let program = program(&mut ebpf)?;
+        program.pin(path.join(PROGRAM)).context("pin program")?;
+        pinned.push(path.join(PROGRAM));
+        let link: FdLink = program.take_link(link_id)?.into();
+        link.pin(path.join(LINK)).context("pin program link")?;
+        Ok(())
+    };
+    if let Err(e) = pin_all() {
+        for pin in pinned {
+            let _ = fs::remove_file(pin);
+        }
+        return Err(e);
+    }
+    Ok((maps, ebpf))
+}
+
+fn open_pins(path: &Path) -> anyhow::Result<[Map; 4]> {
+    let open = |name: &str| {
+        MapData::from_pin(path.join(name))
+            .with_context(|| format!("open pinned sensor in {}", path.display()))
+    };
+    Ok([
+        Map::PerCpuArray(open(COUNTERS_MAP)?),
+        Map::HashMap(open(TARGETS_MAP)?),
+        Map::HashMap(open(MONITORS_MAP)?),
+        Map::HashMap(open(OWNERS_MAP)?),
+    ])
+}
+
+fn create_dir(path: &Path) -> anyhow::Result<File> {
+    fs::create_dir_all(path).with_context(|| format!("create {}", path.display()))?;
+    open_dir(path)
+}
+
+fn open_dir(path: &Path) -> anyhow::Result<File> {
+    File::open(path).with_context(|| format!("open {}", path.display()))
+}
+
+//The kernel fails inserts with BPF_NOEXIST when the key is already there.
+const BPF_NOEXIST: u64 = 1;
+
+//Claim a free slot for `pid`, atomically across the processes sharing the sensor.
+fn claim_slot(owners: &mut HashMap<MapData, u32, u32>, pid: u32) -> anyhow::Result<u32> {
+    for slot in 0..MAX_TARGETS {
+        match owners.insert(slot, pid, BPF_NOEXIST) {
+            Ok(()) => return Ok(slot),
+            Err(MapError::SyscallError(SyscallError { io_error, .. }))
+                if io_error.raw_os_error() == Some(libc::EEXIST) => {}
+            Err(e) => return Err(e).context("claim counters slot"),
+        }
+    }
+    Err(anyhow!("cannot monitor more than {MAX_TARGETS} processes"))
+}
+
+//Exclusive flock on a directory, released when dropped.
+struct PinLock<'a>(&'a File);
+
+impl<'a> PinLock<'a> {
+    fn new(dir: &'a File) -> std::io::Result<Self> {
+        if unsafe { libc::flock(dir.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Self(dir))
+    }
+}
+
+impl Drop for PinLock<'_> {
+    fn drop(&mut self) {
+        unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+    }
+}
@@ -0,0 +1,314 @@
+use std::time::Duration;
+
+use ebsentinel_common::MAX_SYSCALLS;
+use rand::{rngs::StdRng, SeedableRng};
+use rand_distr::{Distribution, Poisson};
+use tokio::{
+    sync::mpsc::{channel, Receiver},
+    task::yield_now,
+    time::sleep,
+};
+
+use crate::{
+    process_data::{DataProcessor, Differentiator, Normalizer},
+    sample_source::{Sample, SampleSource, CHANNEL_CAPACITY},
+};
+
+//x86_64 syscall numbers used by the built-in profiles.
+pub const READ: usize = 0;
+pub const WRITE: usize = 1;
+pub const CLOSE: usize = 3;
+pub const FSTAT: usize = 5;
+pub const MMAP: usize = 9;
+pub const SOCKET: usize = 41;
+pub const CONNECT: usize = 42;
+pub const SENDTO: usize = 44;
+pub const RECVFROM: usize = 45;
+pub const CLONE: usize = 56;
+pub const EXECVE: usize = 59;
+pub const WAIT4: usize = 61;
+pub const FUTEX: usize = 202;
+pub const EPOLL_WAIT: usize = 232;
+pub const OPENAT: usize = 257;
+pub const ACCEPT4: usize = 288;
+
+/// Normal behaviour of a synthetic process, as mean calls per second for each syscall.
+#[derive(Debug, Clone)]
+pub enum Profile {
+    /// The same syscall mix all the time, like a long running server.
+    Steady { rates: Vec<(usize, f32)> },
+    /// `base` all the time plus `burst` during the first `burst_len` of every `period`,
+    /// like a batch job.
+    Bursty {
+        base: Vec<(usize, f32)>,
+        burst: Vec<(usize, f32)>,
+        period: Duration,
+        burst_len: Duration,
+    },
+}
+
+impl Profile {
+    /// An event loop serving small requests.
+    pub fn steady_server() -> Self {
+        Profile::Steady {
+            rates: vec![
+                (EPOLL_WAIT, 400.0),
+                (ACCEPT4, 50.0),
+                (RECVFROM, 200.0),
+                (SENDTO, 200.0),
+                (CLOSE, 50.0),
+                (FUTEX, 100.0),
+            ],
+        }
+    }
+
+    /// Mostly idle, then reads and writes files for a second every ten seconds.
+    pub fn batch_job() -> Self {
+        Profile::Bursty {
+            base: vec![(FUTEX, 20.0), (EPOLL_WAIT, 10.0)],
+            burst: vec![
+                (OPENAT, 100.0),
+                (FSTAT, 100.0),
+                (READ, 2000.0),
+                (WRITE, 1500.0),
+                (MMAP, 50.0),
+                (CLOSE, 100.0),
+            ],
+            period: Duration::from_secs(10),
+            burst_len: Duration::from_secs(1),
+        }
+    }
+
+    fn rates_at(&self, time: Duration) -> Vec<(usize, f32)> {
+        match self {
+            Profile::Steady { rates } => rates.clone(),
+            Profile::Bursty {
+                base,
+                burst,
+                period,
+                burst_len,
+            } => {
+                let mut rates = base.clone();
+                if time.as_nanos() % period.as_nanos().max(1) < burst_len.as_nanos() {
+                    rates.extend(burst);
+                }
+                rates
+            }
+        }
+    }
+}
+
+/// Deviation from the [`Profile`] injected for a while.
+#[derive(Debug, Clone)]
+pub enum Anomaly {
+    /// Multiply the rate of every syscall of the profile by `factor`.
+    Spike { factor: f32 },
+    /// Add syscalls on top of the profile.
+    Extra { rates: Vec<(usize, f32)> },
+    /// The process stops issuing syscalls.
+    Silence,
+}
+
+impl Anomaly {
+    /// A burst of process creation, like a spawned shell running commands.
+    pub fn shell_spawn() -> Self {
+        Anomaly::Extra {
+            rates: vec![(CLONE, 200.0), (EXECVE, 200.0), (WAIT4, 200.0), (OPENAT, 400.0)],
+        }
+    }
+
+    /// Files read and sent to a new connection.
+    pub fn exfiltration() -> Self {
+        Anomaly::Extra {
+            rates: vec![
+                (OPENAT, 200.0),
+                (READ, 2000.0),
+                (SOCKET, 20.0),
+                (CONNECT, 20.0),
+                (SENDTO, 2000.0),
+            ],
+        }
+    }
+}
+
+/// An [`Anomaly`] active for `len` polling ticks starting at tick `start`.
+#[derive(Debug, Clone)]
+pub struct Injection {
+    pub start: usize,
+    pub len: usize,
+    pub anomaly: Anomaly,
+}
+
+/// Generates syscall-rate vectors from a [`Profile`] without loading any eBPF program.
+///
+/// Syscall counts are drawn from a Poisson distribution every polling tick and go through the
+/// same processing as [`crate::proc_mon::ProcMon`], so ticks without any syscall produce no
+/// sample.
+#[derive(Debug, Clone)]
+pub struct SyntheticSource {
+    profile: Profile,
+    injections: Vec<Injection>,
+    polling_rate: Duration,
+    seed: u64,
+    ticks: Option<usize>,
+    realtime: bool,
+}
+
+impl SyntheticSource {
+    pub fn new(profile: Profile, polling_rate: Duration) -> Self {
+        Self {
+            profile,
+            injections: Vec::new(),
+            polling_rate,
+            seed: 42,
+            ticks: None,
+            realtime: false,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Stop after `ticks` polling ticks and close the channel. Runs forever by default.
+    pub fn with_ticks(mut self, ticks: usize) -> Self {
+        self.ticks = Some(ticks);
+        self
+    }
+
+    pub fn with_injection(mut self, injection: Injection) -> Self {
+        self.injections.push(injection);
+        self
+    }
+
+    /// Wait the polling rate between ticks instead of generating as fast as possible.
+    pub fn realtime(mut self, realtime: bool) -> Self {
+        self.realtime = realtime;
+        self
+    }
+
+    /// Whether an anomaly is injected at `tick`.
+    pub fn is_anomalous(&self, tick: usize) -> bool {
+        self.anomaly_at(tick).is_some()
+    }
+
+    fn anomaly_at(&self, tick: usize) -> Option<&Anomaly> {
+        self.injections
+            .iter()
+            .find(|injection| (injection.start..injection.start + injection.len).contains(&tick))
+            .map(|injection| &injection.anomaly)
+    }
+
+    //Add the syscalls issued during `tick` to the cumulative counters.
+    fn tick(&self, tick: usize, rng: &mut StdRng, counts: &mut [u64]) {
+        let mut rates = self.profile.rates_at(self.polling_rate * tick as u32);
+        match self.anomaly_at(tick) {
+            Some(Anomaly::Spike { factor }) => rates.iter_mut().for_each(|(_, rate)| *rate *= factor),
+            Some(Anomaly::Extra { rates: extra }) => rates.extend(extra),
+            Some(Anomaly::Silence) => rates.clear(),
+            None => {}
+        }
+
+        let secs = self.polling_rate.as_secs_f64();
+        for (syscall, rate) in rates {
+            let lambda = rate as f64 * secs;
+            if syscall >= MAX_SYSCALLS as usize || lambda <= 0.0 {
+                continue;
+            }
+            if let Ok(poisson) = Poisson::new(lambda) {
+                let calls: f64 = poisson.sample(rng);
+                counts[syscall] += calls as u64;
+            }
+        }
+    }
+}
+
+impl SampleSource for SyntheticSource {
+    fn run(&mut self) -> anyhow::Result<Receiver<Sample>> {
+        let (tx, rx) = channel(CHANNEL_CAPACITY);
+        let source = self.clone();
+
+        tokio::spawn(async move {
+            let mut rng = StdRng::seed_from_u64(source.seed);
+            let mut differentiator = Differentiator::new(&source.polling_rate);
+            let mut prev = Vec::new();
+            let mut syscall_counts = vec![0; MAX_SYSCALLS as usize];
+            let mut tick = 0;
+            while source.ticks.is_none_or(|ticks| tick < ticks) {
+                source.tick(tick, &mut rng, &mut syscall_counts);
+
+                if syscall_counts != prev {
+                    let rates = differentiator.process(&syscall_counts);
+                    if let Ok(rates) = rates {
+                        let features = Normalizer.process(&rates).unwrap();
+                        //Unlike ProcMon, wait for the consumer instead of dropping samples.
+                        let sample = Sample {
+                            //Simulated time, counted from the first tick.
+                            timestamp_ns: (source.polling_rate * tick as u32).as_nanos() as i64,
+                            counts: syscall_counts.clone(),
+                            rates,
+                            features,
+                        };
+                        if tx.send(sample).await.is_err() {
+                            break;
+                        }
+                    }
+                    prev = syscall_counts.clone();
+                }
+
+                tick += 1;
+                if source.realtime {
+                    sleep(source.polling_rate).await
+                } else {
+                    yield_now().await
+                }
+            }
+        });
+
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Anomaly, Injection, Profile, SyntheticSource, EXECVE};
+    use crate::sample_source::SampleSource;
+
+    async fn collect(mut source: SyntheticSource) -> Vec<Vec<f32>> {
+        let mut rx = source.run().unwrap();
+        let mut samples = Vec::new();
+        while let Some(sample) = rx.recv().await {
+            samples.push(sample.features);
+        }
+        samples
+    }
+
+    #[tokio::test]
+    async fn injected_anomaly_shows_up() {
+        let source = SyntheticSource::new(Profile::steady_server(), Duration::from_millis(100))
+            .with_ticks(20)
+            .with_injection(Injection {
+                start: 10,
+                len: 5,
+                anomaly: Anomaly::shell_spawn(),
+            });
+        assert!(source.is_anomalous(12));
+
+        let samples = collect(source).await;
+        assert_eq!(samples.len(), 20);
+        assert!(samples[..10].iter().all(|sample| sample[EXECVE] == 0.0));
+        assert!(samples[10..15].iter().all(|sample| sample[EXECVE] > 0.0));
+        assert!(samples[15..].iter().all(|sample| sample[EXECVE] == 0.0));
+    }
+
+    #[tokio::test]
+    async fn same_seed_same_samples() {
+        let source = SyntheticSource::new(Profile::batch_job(), Duration::from_millis(100))
+            .with_seed(7)
+            .with_ticks(200);
+        assert_eq!(collect(source.clone()).await, collect(source).await);
+    }
+}
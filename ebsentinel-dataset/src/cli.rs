@@ -0,0 +1,137 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use ebsentinel_db::{Ratios, SplitStrategy, SPLITS};
+
+use crate::{record::Format, select::SampleFilter};
+
+#[derive(Parser)]
+pub struct Cli{
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command{
+    /// Split the recorded samples into the train, valid and test tables
+    Split{
+        #[arg(value_name = "FILE", default_value="ebsentinel.db")]
+        db_file: String,
+        #[command(flatten)]
+        split: SplitArgs,
+    },
+    /// Rewrite the stored features and counters in another layout
+    Convert{
+        #[arg(value_name = "FILE", default_value="ebsentinel.db")]
+        db_file: String,
+        #[arg(long, value_enum, default_value_t = Layout::Sparse)]
+        layout: Layout,
+    },
+    /// Write the samples to a CSV, JSON Lines or Parquet file, one column per syscall
+    Export{
+        #[arg(value_name = "OUTPUT")]
+        output: PathBuf,
+        #[arg(long = "db", value_name = "FILE", default_value="ebsentinel.db")]
+        db_file: String,
+        /// Format of the file [default: from its extension]
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+        /// Write the raw syscall counters instead of the features
+        #[arg(long)]
+        counts: bool,
+    },
+    /// Copy the samples of several databases into one, with their sessions
+    Merge{
+        /// Database the samples are added to, created if missing
+        #[arg(long, short, value_name = "FILE")]
+        output: String,
+        #[arg(value_name = "INPUT", required = true)]
+        inputs: Vec<String>,
+    },
+    /// Copy the samples meeting every given condition into another database
+    Filter{
+        #[arg(value_name = "INPUT")]
+        input: String,
+        #[arg(long, short, value_name = "FILE")]
+        output: String,
+        #[command(flatten)]
+        filter: SampleFilter,
+    },
+    /// Copy the samples into another database, leaving out the repeated vectors
+    Dedup{
+        #[arg(value_name = "INPUT")]
+        input: String,
+        #[arg(long, short, value_name = "FILE")]
+        output: String,
+    },
+    /// Copy as many random samples of every session into another database
+    Subsample{
+        #[arg(value_name = "INPUT")]
+        input: String,
+        #[arg(long, short, value_name = "FILE")]
+        output: String,
+        /// Samples kept per session [default: the size of the smallest session]
+        #[arg(long, value_name = "N")]
+        per_session: Option<usize>,
+        #[arg(long, default_value_t = 42)]
+        seed: u64,
+    },
+    /// Print the split sizes, the syscalls seen, per-syscall statistics and the drift between two splits
+    Inspect{
+        #[arg(value_name = "FILE", default_value="ebsentinel.db")]
+        db_file: String,
+        /// Inspect the raw syscall counters instead of the features
+        #[arg(long)]
+        counts: bool,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+        /// Split the drift is measured against
+        #[arg(long, default_value = "train", value_parser = SPLITS)]
+        baseline: String,
+        /// Split whose drift is measured
+        #[arg(long, default_value = "test", value_parser = SPLITS)]
+        compare: String,
+        /// Histogram bins of the drift measures
+        #[arg(long, default_value_t = 10)]
+        bins: usize,
+    },
+    /// Add the samples of a CSV, JSON Lines or Parquet file, then split them
+    Import{
+        #[arg(value_name = "INPUT")]
+        input: PathBuf,
+        #[arg(long = "db", value_name = "FILE", default_value="ebsentinel.db")]
+        db_file: String,
+        /// Format of the file [default: from its extension]
+        #[arg(long, value_enum)]
+        format: Option<Format>,
+        /// The file holds raw syscall counters instead of features
+        #[arg(long)]
+        counts: bool,
+        /// Polling rate the samples were recorded at, to derive features from counters
+        #[arg(long, default_value_t = 100)]
+        polling_rate_ms: u64,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Layout{
+    /// Every entry, as older databases store them
+    Dense,
+    /// Only the non-zero entries
+    Sparse,
+}
+
+/// Options left out keep the value of the last split, or the default.
+#[derive(clap::Args)]
+pub struct SplitArgs{
+    /// random, contiguous (oldest samples of every session to train) or session (whole sessions)
+    #[arg(long, value_name = "STRATEGY")]
+    pub strategy: Option<SplitStrategy>,
+    /// Ratios of the train, valid and test splits [default: 0.8,0.1,0.1]
+    #[arg(long, value_name = "TRAIN,VALID,TEST")]
+    pub ratios: Option<Ratios>,
+    /// Seed of the shuffles of the random and session strategies [default: 42]
+    #[arg(long)]
+    pub seed: Option<u64>,
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, path::Path};
+
+use ebsentinel_db::{Counts, EbsentinelDb, Row, Session, StoredSample, Syscalls};
+
+/// Copies samples between databases, bringing their sessions along.
+pub struct Copier<'a> {
+    out: &'a mut EbsentinelDb,
+}
+
+impl<'a> Copier<'a> {
+    pub fn new(out: &'a mut EbsentinelDb) -> Self {
+        Self { out }
+    }
+
+    /// Copy the samples of `source` kept by `keep` and return how many were copied.
+    ///
+    /// Only the sessions of copied samples are created, with the metadata they had in `source`.
+    pub fn copy(&mut self, source: &EbsentinelDb, source_path: &str, mut keep: impl FnMut(&StoredSample) -> bool) -> anyhow::Result<usize> {
+        let metadata = source.metadata()?;
+        if self.out.samples()? == 0 && self.out.pending() == 0 {
+            self.out.inherit_metadata(&metadata)?;
+        } else {
+            self.out.check_compatible(&metadata)?;
+        }
+
+        let mut sessions: HashMap<Option<i64>, (Session, Option<i64>)> = source
+            .sessions()?
+            .into_iter()
+            .map(|stored| (Some(stored.session_id), (stored.session, stored.ended_at)))
+            .collect();
+        //Samples recorded before sessions existed.
+        let stem = Path::new(source_path).file_stem().map_or("legacy".to_string(), |stem| stem.to_string_lossy().into_owned());
+        let legacy = Session {
+            name: stem,
+            label: None,
+            tags: vec!["legacy".to_string()],
+            host: "unknown".to_string(),
+            pid: 0,
+            binary: None,
+            binary_sha256: None,
+            kernel: "unknown".to_string(),
+            polling_rate_ms: 100,
+            recorder_version: "unknown".to_string(),
+            started_at: 0,
+        };
+
+        //Source session ids to the ids of their copies, with the end of the copied ones.
+        let mut copies: HashMap<Option<i64>, (i64, Option<i64>)> = HashMap::new();
+        let mut copied = 0;
+        let out = &mut *self.out;
+        source.for_each_sample(|sample| {
+            if !keep(&sample) {
+                return Ok(());
+            }
+            let session_id = match copies.get(&sample.session_id) {
+                Some((session_id, _)) => *session_id,
+                None => {
+                    let (session, ended_at) = sessions.remove(&sample.session_id).unwrap_or_else(|| (legacy.clone(), None));
+                    let session_id = out.start_session(&session)?;
+                    copies.insert(sample.session_id, (session_id, ended_at));
+                    session_id
+                }
+            };
+            out.add_sample(Row {
+                session_id,
+                pid: sample.pid.unwrap_or_default(),
+                timestamp_ns: sample.timestamp_ns.unwrap_or_default(),
+                label: sample.label,
+                syscalls: sample.features.map(Syscalls::new),
+                counts: sample.counts.map(Counts::new),
+                split: None,
+            })?;
+            copied += 1;
+            Ok(())
+        })?;
+
+        self.out.flush()?;
+        for (session_id, ended_at) in copies.into_values() {
+            if let Some(ended_at) = ended_at {
+                self.out.end_session(session_id, ended_at)?;
+            }
+        }
+        Ok(copied)
+    }
+}
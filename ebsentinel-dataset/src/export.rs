@@ -0,0 +1,255 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::bail;
+use arrow_array::{
+    builder::{Float32Builder, Int64Builder, StringBuilder, UInt32Builder, UInt64Builder},
+    ArrayRef, RecordBatch,
+};
+use arrow_schema::{DataType, Field, Schema};
+use ebsentinel_db::EbsentinelDb;
+use parquet::arrow::ArrowWriter;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use crate::record::{Columns, Format, Record, Values, META_COLUMNS};
+
+//Rows of a Parquet record batch.
+const BATCH_ROWS: usize = 4096;
+
+trait RecordWriter {
+    fn write(&mut self, record: &Record) -> anyhow::Result<()>;
+    fn finish(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// Write the samples of `db` to `path` and return how many were written.
+pub fn run(db: &EbsentinelDb, path: &Path, format: Format, values: Values) -> anyhow::Result<usize> {
+    let columns = Columns::of(db)?;
+    let width = columns.names().len();
+    let file = File::create(path)?;
+    let mut writer: Box<dyn RecordWriter> = match format {
+        Format::Csv => Box::new(CsvWriter::new(file, &columns, values)?),
+        Format::Jsonl => Box::new(JsonlWriter {
+            out: BufWriter::new(file),
+            names: columns.names().to_vec(),
+            values,
+        }),
+        Format::Parquet => Box::new(ParquetWriter::new(file, &columns, values)?),
+    };
+
+    let mut written = 0;
+    db.for_each_sample(|sample| {
+        let vector: Option<Vec<f64>> = match values {
+            Values::Features => sample.features.map(|features| features.into_iter().map(f64::from).collect()),
+            Values::Counts => sample.counts.map(|counts| counts.into_iter().map(|count| count as f64).collect()),
+        };
+        //Samples recorded without this vector are left out.
+        let Some(mut values) = vector else {
+            return Ok(());
+        };
+        //Every format writes as many syscall columns, shorter vectors end with zeros.
+        if values.len() > width {
+            bail!("sample {} has {} values, more than the {width} syscall columns", sample.sample_id, values.len());
+        }
+        values.resize(width, 0.0);
+        written += 1;
+        writer.write(&Record {
+            sample_id: Some(sample.sample_id),
+            split: sample.split.map(str::to_string),
+            session: sample.session,
+            label: sample.label,
+            pid: sample.pid,
+            timestamp_ns: sample.timestamp_ns,
+            values,
+        })
+    })?;
+    writer.finish()?;
+    Ok(written)
+}
+
+struct CsvWriter {
+    writer: csv::Writer<File>,
+    values: Values,
+}
+
+impl CsvWriter {
+    fn new(file: File, columns: &Columns, values: Values) -> anyhow::Result<Self> {
+        let mut writer = csv::Writer::from_writer(file);
+        writer.write_record(META_COLUMNS.iter().copied().chain(columns.names().iter().map(String::as_str)))?;
+        Ok(Self { writer, values })
+    }
+}
+
+impl RecordWriter for CsvWriter {
+    fn write(&mut self, record: &Record) -> anyhow::Result<()> {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        let mut fields = vec![
+            optional(record.sample_id.map(|id| id.to_string())),
+            optional(record.split.clone()),
+            optional(record.session.clone()),
+            optional(record.label.clone()),
+            optional(record.pid.map(|pid| pid.to_string())),
+            optional(record.timestamp_ns.map(|ts| ts.to_string())),
+        ];
+        fields.extend(record.values.iter().map(|value| match self.values {
+            Values::Features => (*value as f32).to_string(),
+            Values::Counts => (*value as u64).to_string(),
+        }));
+        self.writer.write_record(&fields)?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+struct JsonlWriter {
+    out: BufWriter<File>,
+    names: Vec<String>,
+    values: Values,
+}
+
+//One flat object per line, keys in column order.
+struct JsonRecord<'a> {
+    record: &'a Record,
+    names: &'a [String],
+    values: Values,
+}
+
+impl Serialize for JsonRecord<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let record = self.record;
+        let mut map = serializer.serialize_map(Some(META_COLUMNS.len() + self.names.len()))?;
+        map.serialize_entry("sample_id", &record.sample_id)?;
+        map.serialize_entry("split", &record.split)?;
+        map.serialize_entry("session", &record.session)?;
+        map.serialize_entry("label", &record.label)?;
+        map.serialize_entry("pid", &record.pid)?;
+        map.serialize_entry("timestamp_ns", &record.timestamp_ns)?;
+        for (name, value) in self.names.iter().zip(&record.values) {
+            match self.values {
+                Values::Features => map.serialize_entry(name, &(*value as f32))?,
+                Values::Counts => map.serialize_entry(name, &(*value as u64))?,
+            }
+        }
+        map.end()
+    }
+}
+
+impl RecordWriter for JsonlWriter {
+    fn write(&mut self, record: &Record) -> anyhow::Result<()> {
+        let json = JsonRecord {
+            record,
+            names: &self.names,
+            values: self.values,
+        };
+        serde_json::to_writer(&mut self.out, &json)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+struct ParquetWriter {
+    writer: ArrowWriter<File>,
+    schema: Arc<Schema>,
+    values: Values,
+    rows: Vec<Record>,
+}
+
+impl ParquetWriter {
+    fn new(file: File, columns: &Columns, values: Values) -> anyhow::Result<Self> {
+        let value_type = match values {
+            Values::Features => DataType::Float32,
+            Values::Counts => DataType::UInt64,
+        };
+        let mut fields = vec![
+            Field::new("sample_id", DataType::Int64, true),
+            Field::new("split", DataType::Utf8, true),
+            Field::new("session", DataType::Utf8, true),
+            Field::new("label", DataType::Utf8, true),
+            Field::new("pid", DataType::UInt32, true),
+            Field::new("timestamp_ns", DataType::Int64, true),
+        ];
+        fields.extend(columns.names().iter().map(|name| Field::new(name, value_type.clone(), false)));
+        let schema = Arc::new(Schema::new(fields));
+        let writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+        Ok(Self {
+            writer,
+            schema,
+            values,
+            rows: Vec::with_capacity(BATCH_ROWS),
+        })
+    }
+
+    fn write_batch(&mut self) -> anyhow::Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let rows = std::mem::take(&mut self.rows);
+        let strings = |field: fn(&Record) -> Option<&str>| -> ArrayRef {
+            let mut builder = StringBuilder::new();
+            rows.iter().for_each(|row| builder.append_option(field(row)));
+            Arc::new(builder.finish())
+        };
+        let int64 = |field: fn(&Record) -> Option<i64>| -> ArrayRef {
+            let mut builder = Int64Builder::new();
+            rows.iter().for_each(|row| builder.append_option(field(row)));
+            Arc::new(builder.finish())
+        };
+        let mut pids = UInt32Builder::new();
+        rows.iter().for_each(|row| pids.append_option(row.pid));
+
+        let mut arrays: Vec<ArrayRef> = vec![
+            int64(|row| row.sample_id),
+            strings(|row| row.split.as_deref()),
+            strings(|row| row.session.as_deref()),
+            strings(|row| row.label.as_deref()),
+            Arc::new(pids.finish()),
+            int64(|row| row.timestamp_ns),
+        ];
+        let width = self.schema.fields().len() - META_COLUMNS.len();
+        for column in 0..width {
+            let array: ArrayRef = match self.values {
+                Values::Features => {
+                    let mut builder = Float32Builder::with_capacity(rows.len());
+                    rows.iter().for_each(|row| builder.append_value(row.values[column] as f32));
+                    Arc::new(builder.finish())
+                }
+                Values::Counts => {
+                    let mut builder = UInt64Builder::with_capacity(rows.len());
+                    rows.iter().for_each(|row| builder.append_value(row.values[column] as u64));
+                    Arc::new(builder.finish())
+                }
+            };
+            arrays.push(array);
+        }
+        self.writer.write(&RecordBatch::try_new(self.schema.clone(), arrays)?)?;
+        Ok(())
+    }
+}
+
+impl RecordWriter for ParquetWriter {
+    fn write(&mut self, record: &Record) -> anyhow::Result<()> {
+        self.rows.push(record.clone());
+        if self.rows.len() == BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> anyhow::Result<()> {
+        self.write_batch()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    fs::File,
+    io::{BufRead, BufReader},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Context};
+use arrow_array::{
+    cast::AsArray,
+    types::{Float32Type, Float64Type, Int32Type, Int64Type, UInt32Type, UInt64Type},
+    Array, RecordBatch, RecordBatchReader,
+};
+use arrow_schema::DataType;
+use ebsentinel_db::{Counts, EbsentinelDb, Row, Session, Syscalls, RATE_MAX_NORM, SPLITS};
+use ebsentinel_features::pipeline::{Pipeline, Preprocessing};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde_json::{Map, Value};
+
+use crate::record::{Columns, Format, Record, Values};
+
+type Records = Box<dyn Iterator<Item = anyhow::Result<Record>>>;
+
+//Where a column of a file goes in a record.
+#[derive(Clone, Copy)]
+enum Target {
+    SampleId,
+    Split,
+    Session,
+    Label,
+    Pid,
+    Timestamp,
+    Syscall(usize),
+}
+
+impl Target {
+    fn of(name: &str, columns: &Columns) -> anyhow::Result<Self> {
+        Ok(match name {
+            "sample_id" => Target::SampleId,
+            "split" => Target::Split,
+            "session" => Target::Session,
+            "label" => Target::Label,
+            "pid" => Target::Pid,
+            "timestamp_ns" => Target::Timestamp,
+            _ => Target::Syscall(columns.index(name).ok_or_else(|| anyhow!("unknown column {name}"))?),
+        })
+    }
+}
+
+//A session of the file, with the pipeline deriving the features of its counters.
+struct Imported {
+    session_id: i64,
+    ended_at: i64,
+    pipeline: Pipeline,
+}
+
+/// Add the samples of `path` to `db` and return how many were added.
+///
+/// Every session of the file becomes a session of the database. Samples join the split of their
+/// `split` column, or a split at the next split. Features must be rates divided by the largest
+/// one, like ebsentinel-rec records them; the features of raw counters are derived the same way,
+/// and the first counters of every session, which have no predecessor, only start the derivation.
+pub fn run(db: &mut EbsentinelDb, path: &Path, format: Format, values: Values, polling_rate_ms: u64) -> anyhow::Result<usize> {
+    let preprocessing = db.metadata()?.preprocessing;
+    if preprocessing != RATE_MAX_NORM {
+        bail!("the database holds features preprocessed with {preprocessing}, only {RATE_MAX_NORM} samples can be imported");
+    }
+    let columns = Columns::new(true);
+    let names = columns.names().to_vec();
+    let records = match format {
+        Format::Csv => csv_records(path, &columns)?,
+        Format::Jsonl => jsonl_records(path, columns),
+        Format::Parquet => parquet_records(path, &columns)?,
+    };
+    let default_session = path.file_stem().map_or("import".to_string(), |stem| stem.to_string_lossy().into_owned());
+
+    let mut sessions: HashMap<String, Imported> = HashMap::new();
+    let mut imported = 0;
+    for (line, record) in records.enumerate() {
+        let record = record?;
+        let context = || format!("sample {} of {}", line + 1, path.display());
+        let name = record.session.clone().unwrap_or_else(|| default_session.clone());
+        let timestamp_ns = record.timestamp_ns.unwrap_or_default();
+        let split = record
+            .split
+            .as_deref()
+            .map(|split| SPLITS.into_iter().find(|known| *known == split).with_context(|| format!("unknown split {split}")))
+            .transpose()
+            .with_context(context)?;
+        let session = match sessions.entry(name) {
+            Entry::Occupied(entry) => {
+                let session = entry.into_mut();
+                if values == Values::Counts && timestamp_ns < session.ended_at {
+                    bail!("{}: the counters of its session are not in time order", context());
+                }
+                session.ended_at = session.ended_at.max(timestamp_ns);
+                session
+            }
+            Entry::Vacant(entry) => {
+                let session = Session {
+                    name: entry.key().clone(),
+                    label: record.label.clone(),
+                    tags: vec!["imported".to_string()],
+                    host: "unknown".to_string(),
+                    pid: record.pid.unwrap_or_default(),
+                    binary: None,
+                    binary_sha256: None,
+                    kernel: "unknown".to_string(),
+                    polling_rate_ms,
+                    recorder_version: format!("ebsentinel-dataset {}", env!("CARGO_PKG_VERSION")),
+                    started_at: record.timestamp_ns.unwrap_or_else(now_ns),
+                };
+                let imported = Imported {
+                    session_id: db.start_session(&session)?,
+                    ended_at: timestamp_ns,
+                    pipeline: Pipeline::new(Preprocessing::default(), Duration::from_millis(polling_rate_ms)),
+                };
+                entry.insert(imported)
+            }
+        };
+
+        let (syscalls, counts) = match values {
+            Values::Features => (Some(features(&record.values, &names).with_context(context)?), None),
+            Values::Counts => {
+                let counts = counts(&record.values, &names).with_context(context)?;
+                let Some(features) = session.pipeline.push(timestamp_ns, &counts) else {
+                    continue;
+                };
+                (Some(features), Some(Counts::new(counts)))
+            }
+        };
+        db.add_sample(Row {
+            session_id: session.session_id,
+            pid: record.pid.unwrap_or_default(),
+            timestamp_ns,
+            label: record.label,
+            syscalls: syscalls.map(Syscalls::new),
+            counts,
+            split,
+        })?;
+        imported += 1;
+    }
+    db.flush()?;
+    for session in sessions.into_values() {
+        db.end_session(session.session_id, session.ended_at)?;
+    }
+    Ok(imported)
+}
+
+//Features of the file, which must be max-normalized rates.
+fn features(values: &[f64], names: &[String]) -> anyhow::Result<Vec<f32>> {
+    if let Some((nr, value)) = values.iter().enumerate().find(|(_, value)| !(0.0..=1.0).contains(*value)) {
+        bail!("feature {} is {value}, features are between 0 and 1, import raw counters with --counts", names[nr]);
+    }
+    Ok(values.iter().map(|value| *value as f32).collect())
+}
+
+fn counts(values: &[f64], names: &[String]) -> anyhow::Result<Vec<u64>> {
+    if let Some((nr, value)) = values.iter().enumerate().find(|(_, value)| **value < 0.0 || value.fract() != 0.0) {
+        bail!("counter {} is {value}, counters are non-negative integers", names[nr]);
+    }
+    Ok(values.iter().map(|value| *value as u64).collect())
+}
+
+fn now_ns() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as i64)
+}
+
+fn empty_record(columns: &Columns) -> Record {
+    Record {
+        values: vec![0.0; columns.names().len()],
+        ..Record::default()
+    }
+}
+
+fn csv_records(path: &Path, columns: &Columns) -> anyhow::Result<Records> {
+    let mut reader = csv::Reader::from_path(path)?;
+    let targets = reader
+        .headers()?
+        .iter()
+        .map(|name| Target::of(name, columns))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let empty = empty_record(columns);
+
+    Ok(Box::new(reader.into_records().map(move |row| {
+        let row = row?;
+        let mut record = empty.clone();
+        for (target, field) in targets.iter().zip(row.iter()) {
+            let text = (!field.is_empty()).then(|| field.to_string());
+            match *target {
+                Target::SampleId => record.sample_id = text.map(|id| id.parse()).transpose()?,
+                Target::Split => record.split = text,
+                Target::Session => record.session = text,
+                Target::Label => record.label = text,
+                Target::Pid => record.pid = text.map(|pid| pid.parse()).transpose()?,
+                Target::Timestamp => record.timestamp_ns = text.map(|ts| ts.parse()).transpose()?,
+                Target::Syscall(nr) => record.values[nr] = text.map(|value| value.parse()).transpose()?.unwrap_or_default(),
+            }
+        }
+        Ok(record)
+    })))
+}
+
+fn jsonl_records(path: &Path, columns: Columns) -> Records {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Box::new(std::iter::once(Err(e.into()))),
+    };
+    let empty = empty_record(&columns);
+
+    Box::new(BufReader::new(file).lines().filter(|line| !matches!(line, Ok(line) if line.trim().is_empty())).map(
+        move |line| {
+            let object: Map<String, Value> = serde_json::from_str(&line?)?;
+            let mut record = empty.clone();
+            for (name, value) in object {
+                let text = value.as_str().map(str::to_string);
+                let int = value.as_i64();
+                match Target::of(&name, &columns)? {
+                    Target::SampleId => record.sample_id = int,
+                    Target::Split => record.split = text,
+                    Target::Session => record.session = text,
+                    Target::Label => record.label = text,
+                    Target::Pid => record.pid = int.map(u32::try_from).transpose()?,
+                    Target::Timestamp => record.timestamp_ns = int,
+                    Target::Syscall(nr) => {
+                        record.values[nr] = match value {
+                            Value::Null => 0.0,
+                            value => value.as_f64().with_context(|| format!("{name} is not a number"))?,
+                        }
+                    }
+                }
+            }
+            Ok(record)
+        },
+    ))
+}
+
+fn parquet_records(path: &Path, columns: &Columns) -> anyhow::Result<Records> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?.build()?;
+    let targets = reader
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| Target::of(field.name(), columns))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let empty = empty_record(columns);
+
+    Ok(Box::new(reader.flat_map(move |batch| {
+        let records = batch
+            .map_err(anyhow::Error::from)
+            .and_then(|batch| batch_records(&batch, &targets, &empty));
+        match records {
+            Ok(records) => records.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        }
+    })))
+}
+
+fn batch_records(batch: &RecordBatch, targets: &[Target], empty: &Record) -> anyhow::Result<Vec<Record>> {
+    let mut records = vec![empty.clone(); batch.num_rows()];
+    for (target, array) in targets.iter().zip(batch.columns()) {
+        for (row, record) in records.iter_mut().enumerate() {
+            match *target {
+                Target::SampleId => record.sample_id = int(array, row)?,
+                Target::Split => record.split = text(array, row)?,
+                Target::Session => record.session = text(array, row)?,
+                Target::Label => record.label = text(array, row)?,
+                Target::Pid => record.pid = int(array, row)?.map(u32::try_from).transpose()?,
+                Target::Timestamp => record.timestamp_ns = int(array, row)?,
+                Target::Syscall(nr) => record.values[nr] = float(array, row)?.unwrap_or_default(),
+            }
+        }
+    }
+    Ok(records)
+}
+
+fn text(array: &dyn Array, row: usize) -> anyhow::Result<Option<String>> {
+    if array.is_null(row) {
+        return Ok(None);
+    }
+    Ok(Some(match array.data_type() {
+        DataType::Utf8 => array.as_string::<i32>().value(row).to_string(),
+        DataType::LargeUtf8 => array.as_string::<i64>().value(row).to_string(),
+        other => bail!("expected a string column, found {other}"),
+    }))
+}
+
+fn int(array: &dyn Array, row: usize) -> anyhow::Result<Option<i64>> {
+    if array.is_null(row) {
+        return Ok(None);
+    }
+    Ok(Some(match array.data_type() {
+        DataType::Int32 => array.as_primitive::<Int32Type>().value(row) as i64,
+        DataType::Int64 => array.as_primitive::<Int64Type>().value(row),
+        DataType::UInt32 => array.as_primitive::<UInt32Type>().value(row) as i64,
+        DataType::UInt64 => array.as_primitive::<UInt64Type>().value(row) as i64,
+        other => bail!("expected an integer column, found {other}"),
+    }))
+}
+
+fn float(array: &dyn Array, row: usize) -> anyhow::Result<Option<f64>> {
+    if array.is_null(row) {
+        return Ok(None);
+    }
+    Ok(Some(match array.data_type() {
+        DataType::Float32 => array.as_primitive::<Float32Type>().value(row) as f64,
+        DataType::Float64 => array.as_primitive::<Float64Type>().value(row),
+        _ => int(array, row)?.unwrap_or_default() as f64,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use ebsentinel_db::EbsentinelDb;
+
+    use super::run;
+    use crate::record::{Format, Values};
+
+    #[test]
+    fn imports_counts_ready_to_train() {
+        let path = std::env::temp_dir().join(format!("ebsentinel-import-{}.csv", std::process::id()));
+        let mut db = EbsentinelDb::new(":memory:").unwrap();
+
+        fs::write(&path, "session,split,timestamp_ns,read,write\na,,0,0,0\na,,100000000,4,2\na,test,200000000,5,6\n").unwrap();
+        //The first counters only start the derivation of the features.
+        assert_eq!(run(&mut db, &path, Format::Csv, Values::Counts, 100).unwrap(), 2);
+        assert_eq!(db.split_sizes().unwrap(), [0, 0, 1]);
+        assert_eq!(db.missing_features("test").unwrap(), 0);
+        assert_eq!(db.derive_features("test", &Default::default()).unwrap()[0][..2], [0.25, 1.0]);
+
+        fs::write(&path, "session,read\nb,2.5\n").unwrap();
+        assert!(run(&mut db, &path, Format::Csv, Values::Features, 100).is_err());
+        assert!(run(&mut db, &path, Format::Csv, Values::Counts, 100).is_err());
+        fs::write(&path, "session,split,read\nb,holdout,0.5\n").unwrap();
+        assert!(run(&mut db, &path, Format::Csv, Values::Features, 100).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
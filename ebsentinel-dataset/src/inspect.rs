@@ -0,0 +1,328 @@
+use anyhow::bail;
+use ebsentinel_db::{EbsentinelDb, SPLITS};
+use serde::Serialize;
+
+use crate::record::{Columns, Values};
+
+//Proportion given to empty histogram bins, so the drift stays finite.
+const MIN_PROPORTION: f64 = 1e-4;
+
+/// Everything `inspect` finds out about the samples of a database.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub values: &'static str,
+    pub splits: Vec<SplitSize>,
+    /// Samples in no split, e.g. recorded after the last split.
+    pub unsplit: usize,
+    /// Samples recorded without the inspected vector.
+    pub skipped: usize,
+    pub syscalls_total: usize,
+    pub syscalls_seen: usize,
+    pub baseline: String,
+    pub compare: String,
+    /// Syscalls of the compared split never seen in the baseline one.
+    pub unseen_in_baseline: Vec<String>,
+    /// One entry per syscall seen in any sample.
+    pub syscalls: Vec<SyscallStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SplitSize {
+    pub split: &'static str,
+    pub samples: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyscallStats {
+    pub nr: usize,
+    pub name: String,
+    /// Samples where the syscall is non-zero.
+    pub seen: usize,
+    pub mean: f64,
+    pub variance: f64,
+    pub min: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+    /// Population stability index of the compared split against the baseline one.
+    pub psi: Option<f64>,
+    /// Kullback-Leibler divergence of the compared split from the baseline one.
+    pub kl: Option<f64>,
+}
+
+//Values of one syscall, zeros are only counted since most syscalls are zero most of the time.
+#[derive(Debug, Clone, Default)]
+struct Column {
+    zeros: usize,
+    nonzero: Vec<f64>,
+}
+
+impl Column {
+    fn push(&mut self, value: f64) {
+        if value == 0.0 {
+            self.zeros += 1;
+        } else {
+            self.nonzero.push(value);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.zeros + self.nonzero.len()
+    }
+
+    fn merge<'a>(columns: impl Iterator<Item = &'a Column>) -> Column {
+        columns.fold(Column::default(), |mut merged, column| {
+            merged.zeros += column.zeros;
+            merged.nonzero.extend(&column.nonzero);
+            merged
+        })
+    }
+}
+
+//A column with its non-zero values sorted.
+struct Sorted {
+    zeros: usize,
+    values: Vec<f64>,
+    negatives: usize,
+}
+
+impl Sorted {
+    fn new(column: &Column) -> Self {
+        let mut values = column.nonzero.clone();
+        values.sort_by(f64::total_cmp);
+        let negatives = values.partition_point(|value| *value < 0.0);
+        Self {
+            zeros: column.zeros,
+            values,
+            negatives,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.zeros + self.values.len()
+    }
+
+    //The value of `rank` in the sorted column, the zeros sitting between negative and positive values.
+    fn at(&self, rank: usize) -> f64 {
+        if rank < self.negatives {
+            self.values[rank]
+        } else if rank < self.negatives + self.zeros {
+            0.0
+        } else {
+            self.values[rank - self.zeros]
+        }
+    }
+
+    //Nearest-rank percentile, `p` in [0, 1].
+    fn percentile(&self, p: f64) -> f64 {
+        let rank = (p * self.len() as f64).ceil() as usize;
+        self.at(rank.clamp(1, self.len()) - 1)
+    }
+}
+
+/// Inspect the samples of `db`, comparing the distributions of the `compare` split against the `baseline` one over `bins` bins.
+pub fn run(db: &EbsentinelDb, values: Values, baseline: &str, compare: &str, bins: usize) -> anyhow::Result<Report> {
+    if bins == 0 {
+        bail!("at least one bin is needed to compare the splits");
+    }
+    let Some(baseline_idx) = SPLITS.iter().position(|split| *split == baseline) else {
+        bail!("unknown split {baseline}");
+    };
+    let Some(compare_idx) = SPLITS.iter().position(|split| *split == compare) else {
+        bail!("unknown split {compare}");
+    };
+    let names = Columns::of(db)?;
+
+    //Columns of every split, then of the samples in no split.
+    let mut groups: Vec<Vec<Column>> = vec![Vec::new(); SPLITS.len() + 1];
+    let mut skipped = 0;
+    db.for_each_sample(|sample| {
+        let vector: Option<Vec<f64>> = match values {
+            Values::Features => sample.features.map(|features| features.into_iter().map(f64::from).collect()),
+            Values::Counts => sample.counts.map(|counts| counts.into_iter().map(|count| count as f64).collect()),
+        };
+        let Some(vector) = vector else {
+            skipped += 1;
+            return Ok(());
+        };
+        let group = sample
+            .split
+            .and_then(|split| SPLITS.iter().position(|name| *name == split))
+            .unwrap_or(SPLITS.len());
+        let columns = &mut groups[group];
+        if columns.len() < vector.len() {
+            //Columns appearing later start with zeros for the samples seen so far.
+            let zeros = columns.first().map_or(0, Column::len);
+            columns.resize(vector.len(), Column { zeros, nonzero: Vec::new() });
+        }
+        let len = columns.len();
+        for (column, value) in columns.iter_mut().zip(vector.into_iter().chain(std::iter::repeat(0.0)).take(len)) {
+            column.push(value);
+        }
+        Ok(())
+    })?;
+
+    let samples = |group: usize| groups[group].first().map_or(0, Column::len);
+    let width = groups.iter().map(Vec::len).max().unwrap_or_default();
+    let empty = Column::default();
+    let column = |group: usize, nr: usize| groups[group].get(nr).unwrap_or(&empty);
+    let name = |nr: usize| names.names().get(nr).cloned().unwrap_or_else(|| format!("syscall_{nr}"));
+
+    let mut syscalls = Vec::new();
+    let mut unseen_in_baseline = Vec::new();
+    for nr in 0..width {
+        let all = Column::merge((0..groups.len()).map(|group| column(group, nr)));
+        if all.nonzero.is_empty() {
+            continue;
+        }
+        let (base, comp) = (column(baseline_idx, nr), column(compare_idx, nr));
+        if base.nonzero.is_empty() && !comp.nonzero.is_empty() {
+            unseen_in_baseline.push(name(nr));
+        }
+        let (psi, kl) = match drift(base, comp, bins) {
+            Some((psi, kl)) => (Some(psi), Some(kl)),
+            None => (None, None),
+        };
+
+        let count = all.len() as f64;
+        let mean = all.nonzero.iter().sum::<f64>() / count;
+        let squares = all.nonzero.iter().map(|value| (value - mean).powi(2)).sum::<f64>() + all.zeros as f64 * mean.powi(2);
+        let sorted = Sorted::new(&all);
+        syscalls.push(SyscallStats {
+            nr,
+            name: name(nr),
+            seen: all.nonzero.len(),
+            mean,
+            variance: squares / count,
+            min: sorted.at(0),
+            p50: sorted.percentile(0.5),
+            p90: sorted.percentile(0.9),
+            p99: sorted.percentile(0.99),
+            max: sorted.at(sorted.len() - 1),
+            psi,
+            kl,
+        });
+    }
+
+    Ok(Report {
+        values: match values {
+            Values::Features => "features",
+            Values::Counts => "counts",
+        },
+        splits: SPLITS
+            .iter()
+            .enumerate()
+            .map(|(group, split)| SplitSize { split, samples: samples(group) })
+            .collect(),
+        unsplit: samples(SPLITS.len()),
+        skipped,
+        syscalls_total: names.names().len().max(width),
+        syscalls_seen: syscalls.len(),
+        baseline: baseline.to_string(),
+        compare: compare.to_string(),
+        unseen_in_baseline,
+        syscalls,
+    })
+}
+
+//PSI and KL divergence of `compare` against `baseline`, over equal-width bins spanning both.
+fn drift(baseline: &Column, compare: &Column, bins: usize) -> Option<(f64, f64)> {
+    if baseline.len() == 0 || compare.len() == 0 {
+        return None;
+    }
+    let values = || baseline.nonzero.iter().chain(&compare.nonzero).copied();
+    let low = values().fold(0.0, f64::min);
+    let high = values().fold(0.0, f64::max);
+
+    let histogram = |column: &Column| {
+        let mut counts = vec![0usize; bins];
+        let bin = |value: f64| {
+            if high > low {
+                (((value - low) / (high - low) * bins as f64) as usize).min(bins - 1)
+            } else {
+                0
+            }
+        };
+        counts[bin(0.0)] += column.zeros;
+        column.nonzero.iter().for_each(|value| counts[bin(*value)] += 1);
+        counts
+            .into_iter()
+            .map(|count| (count as f64 / column.len() as f64).max(MIN_PROPORTION))
+            .collect::<Vec<_>>()
+    };
+    let (expected, actual) = (histogram(baseline), histogram(compare));
+    let psi = expected.iter().zip(&actual).map(|(e, a)| (a - e) * (a / e).ln()).sum();
+    let kl = expected.iter().zip(&actual).map(|(e, a)| e * (e / a).ln()).sum();
+    Some((psi, kl))
+}
+
+/// Print `report` as tables.
+pub fn print(report: &Report) {
+    let splits: Vec<String> = report.splits.iter().map(|size| format!("{} {}", size.split, size.samples)).collect();
+    println!("samples: {}, unsplit {}", splits.join(", "), report.unsplit);
+    if report.skipped > 0 {
+        println!("{} samples without {} skipped", report.skipped, report.values);
+    }
+    println!("syscalls seen: {} of {}", report.syscalls_seen, report.syscalls_total);
+    if !report.unseen_in_baseline.is_empty() {
+        println!("seen in {} but not in {}: {}", report.compare, report.baseline, report.unseen_in_baseline.join(", "));
+    }
+    println!("drift of {} against {} (PSI above 0.1 is a moderate shift, above 0.25 a large one)", report.compare, report.baseline);
+    println!();
+
+    println!(
+        "{:<24}{:>8}{:>12}{:>12}{:>12}{:>12}{:>12}{:>12}{:>12}{:>10}{:>10}",
+        "syscall", "seen", "mean", "variance", "min", "p50", "p90", "p99", "max", "psi", "kl"
+    );
+    let drift = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{value:.4}"));
+    for stats in &report.syscalls {
+        println!(
+            "{:<24}{:>8}{:>12.4}{:>12.4}{:>12.4}{:>12.4}{:>12.4}{:>12.4}{:>12.4}{:>10}{:>10}",
+            stats.name,
+            stats.seen,
+            stats.mean,
+            stats.variance,
+            stats.min,
+            stats.p50,
+            stats.p90,
+            stats.p99,
+            stats.max,
+            drift(stats.psi),
+            drift(stats.kl)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{drift, Column, Sorted};
+
+    fn column(values: &[f64]) -> Column {
+        let mut column = Column::default();
+        values.iter().for_each(|value| column.push(*value));
+        column
+    }
+
+    #[test]
+    fn percentiles_count_the_zeros() {
+        let sorted = Sorted::new(&column(&[3.0, 0.0, -1.0, 0.0, 2.0]));
+        let ranks: Vec<f64> = (0..sorted.len()).map(|rank| sorted.at(rank)).collect();
+        assert_eq!(ranks, [-1.0, 0.0, 0.0, 2.0, 3.0]);
+        assert_eq!(sorted.percentile(0.5), 0.0);
+        assert_eq!(sorted.percentile(0.99), 3.0);
+        assert_eq!(sorted.percentile(0.0), -1.0);
+    }
+
+    #[test]
+    fn drift_grows_with_the_shift() {
+        let baseline = column(&[0.0, 0.1, 0.2, 0.3, 0.4, 0.5]);
+        let (psi, kl) = drift(&baseline, &baseline, 10).unwrap();
+        assert!(psi.abs() < 1e-12 && kl.abs() < 1e-12);
+
+        let near = drift(&baseline, &column(&[0.0, 0.1, 0.2, 0.3, 0.4, 0.6]), 10).unwrap();
+        let far = drift(&baseline, &column(&[0.9, 1.0, 1.0, 0.9, 1.0, 1.0]), 10).unwrap();
+        assert!(near.0 < far.0 && near.1 < far.1);
+        assert!(drift(&baseline, &Column::default(), 10).is_none());
+    }
+}
@@ -0,0 +1,133 @@
+use clap::Parser;
+use cli::{Cli, Command, Layout};
+use std::path::Path;
+
+use anyhow::bail;
+use copy::Copier;
+use ebsentinel_db::{EbsentinelDb, SplitConfig, StoredSample, DENSE_LAYOUT, SPARSE_LAYOUT, SPLITS};
+use record::{Format, Values};
+use select::Dedup;
+mod cli;
+mod copy;
+mod export;
+mod import;
+mod inspect;
+mod record;
+mod select;
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Command::Split { db_file, split } => {
+            let mut db = EbsentinelDb::new(&db_file)?;
+            let mut config = db.split_config()?.unwrap_or_default();
+            config.strategy = split.strategy.unwrap_or(config.strategy);
+            config.ratios = split.ratios.unwrap_or(config.ratios);
+            config.seed = split.seed.unwrap_or(config.seed);
+
+            let sizes = db.split(&config)?;
+            print_split(&config, sizes);
+        }
+        Command::Convert { db_file, layout } => {
+            let layout = match layout {
+                Layout::Dense => DENSE_LAYOUT,
+                Layout::Sparse => SPARSE_LAYOUT,
+            };
+            let mut db = EbsentinelDb::new(&db_file)?;
+            let before = db.size()?;
+            let converted = db.convert(layout)?;
+            println!("{converted} vectors converted to {layout}, {before} -> {} bytes", db.size()?);
+        }
+        Command::Export { output, db_file, format, counts } => {
+            let format = Format::resolve(format, &output)?;
+            let db = EbsentinelDb::new(&db_file)?;
+            let written = export::run(&db, &output, format, values(counts))?;
+            println!("{written} samples written to {}", output.display());
+        }
+        Command::Merge { output, inputs } => {
+            let mut out = open_output(&output, &inputs)?;
+            let mut config = out.split_config()?;
+            let mut copier = Copier::new(&mut out);
+            for input in &inputs {
+                let db = EbsentinelDb::new(input)?;
+                config = config.or(db.split_config()?);
+                let copied = copier.copy(&db, input, |_| true)?;
+                println!("{copied} samples copied from {input}");
+            }
+            run_split(&mut out, &config.unwrap_or_default())?;
+        }
+        Command::Filter { input, output, filter } => {
+            copy_into(&input, &output, filter.matcher())?;
+        }
+        Command::Dedup { input, output } => {
+            let mut dedup = Dedup::default();
+            copy_into(&input, &output, |sample| dedup.first(sample))?;
+        }
+        Command::Subsample { input, output, per_session, seed } => {
+            let picked = select::balance(&EbsentinelDb::new(&input)?, per_session, seed)?;
+            copy_into(&input, &output, |sample| picked.contains(&sample.sample_id))?;
+        }
+        Command::Inspect { db_file, counts, json, baseline, compare, bins } => {
+            let db = EbsentinelDb::new(&db_file)?;
+            let report = inspect::run(&db, values(counts), &baseline, &compare, bins)?;
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                inspect::print(&report);
+            }
+        }
+        Command::Import { input, db_file, format, counts, polling_rate_ms } => {
+            let format = Format::resolve(format, &input)?;
+            let mut db = EbsentinelDb::new(&db_file)?;
+            let imported = import::run(&mut db, &input, format, values(counts), polling_rate_ms)?;
+            println!("{imported} samples imported from {}", input.display());
+            let config = db.split_config()?.unwrap_or_default();
+            run_split(&mut db, &config)?;
+        }
+    }
+    Ok(())
+}
+
+fn values(counts: bool) -> Values {
+    if counts {
+        Values::Counts
+    } else {
+        Values::Features
+    }
+}
+
+//Open the database samples are copied to, which must not be one of the inputs.
+fn open_output(output: &str, inputs: &[String]) -> anyhow::Result<EbsentinelDb> {
+    if let Ok(output_path) = Path::new(output).canonicalize() {
+        for input in inputs {
+            if Path::new(input).canonicalize().is_ok_and(|input_path| input_path == output_path) {
+                bail!("{input} is both an input and the output");
+            }
+        }
+    }
+    EbsentinelDb::new(output)
+}
+
+//Copy the samples of `input` kept by `keep` into `output`, then split it like `input`.
+fn copy_into(input: &str, output: &str, keep: impl FnMut(&StoredSample) -> bool) -> anyhow::Result<()> {
+    let mut out = open_output(output, &[input.to_string()])?;
+    let db = EbsentinelDb::new(input)?;
+    let config = out.split_config()?.or(db.split_config()?).unwrap_or_default();
+    let copied = Copier::new(&mut out).copy(&db, input, keep)?;
+    println!("{copied} of {} samples copied to {output}", db.samples()?);
+    run_split(&mut out, &config)
+}
+
+//Assign the samples that are in no split yet, the other ones stay where they are.
+fn run_split(db: &mut EbsentinelDb, config: &SplitConfig) -> anyhow::Result<()> {
+    let sizes = db.split_new(config)?;
+    print_split(config, sizes);
+    Ok(())
+}
+
+fn print_split(config: &SplitConfig, sizes: [usize; 3]) {
+    println!("{} split, ratios {}, seed {}", config.strategy, config.ratios, config.seed);
+    for (table, size) in SPLITS.iter().zip(sizes) {
+        println!("{table}: {size}");
+    }
+}
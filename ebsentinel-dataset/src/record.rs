@@ -0,0 +1,110 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::anyhow;
+use clap::ValueEnum;
+use ebsentinel_common::{syscalls::syscall_name, MAX_SYSCALLS};
+use ebsentinel_db::{EbsentinelDb, Metadata};
+
+/// Columns of every file, before one column per syscall.
+pub const META_COLUMNS: [&str; 6] = ["sample_id", "split", "session", "label", "pid", "timestamp_ns"];
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl Format {
+    /// The format given, or the one of the extension of `path`.
+    pub fn resolve(format: Option<Format>, path: &Path) -> anyhow::Result<Self> {
+        if let Some(format) = format {
+            return Ok(format);
+        }
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Ok(Format::Csv),
+            Some("jsonl" | "ndjson") => Ok(Format::Jsonl),
+            Some("parquet") => Ok(Format::Parquet),
+            _ => Err(anyhow!("cannot guess the format of {}, use --format", path.display())),
+        }
+    }
+}
+
+/// Which vector of the samples the syscall columns hold.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Values {
+    Features,
+    Counts,
+}
+
+/// One sample in a file, with its vector widened to f64 whatever it holds.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Record {
+    pub sample_id: Option<i64>,
+    pub split: Option<String>,
+    pub session: Option<String>,
+    pub label: Option<String>,
+    pub pid: Option<u32>,
+    pub timestamp_ns: Option<i64>,
+    pub values: Vec<f64>,
+}
+
+/// Names of the syscall columns, indexed by syscall number.
+pub struct Columns {
+    names: Vec<String>,
+    indices: HashMap<String, usize>,
+}
+
+impl Columns {
+    /// Columns named after the syscalls of this host, or by number if the samples were recorded with other syscall numbers.
+    pub fn new(named: bool) -> Self {
+        let names: Vec<String> = (0..MAX_SYSCALLS as usize)
+            .map(|nr| match syscall_name(nr) {
+                Some(name) if named => name.to_string(),
+                _ => format!("syscall_{nr}"),
+            })
+            .collect();
+        let mut indices: HashMap<String, usize> = names.iter().cloned().zip(0..).collect();
+        //Numbered columns are always understood.
+        indices.extend((0..names.len()).map(|nr| (format!("syscall_{nr}"), nr)));
+        Self { names, indices }
+    }
+
+    /// Columns of the samples of `db`, named if they were recorded with the syscall numbers of this host.
+    pub fn of(db: &EbsentinelDb) -> anyhow::Result<Self> {
+        let metadata = db.metadata()?;
+        Ok(Self::new(metadata.syscall_table_hash == Metadata::current().syscall_table_hash))
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.indices.get(name).copied()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{Columns, Format};
+
+    #[test]
+    fn columns_accept_names_and_numbers() {
+        let columns = Columns::new(true);
+        assert_eq!(columns.names()[0], "read");
+        assert_eq!(columns.index("write"), Some(1));
+        assert_eq!(columns.index("syscall_1"), Some(1));
+        assert_eq!(columns.index("nope"), None);
+        assert_eq!(Columns::new(false).names()[0], "syscall_0");
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert!(Format::resolve(None, Path::new("a.ndjson")).unwrap() == Format::Jsonl);
+        assert!(Format::resolve(Some(Format::Csv), Path::new("a.parquet")).unwrap() == Format::Csv);
+        assert!(Format::resolve(None, Path::new("a.db")).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::{HashMap, HashSet};
+
+use ebsentinel_db::{EbsentinelDb, StoredSample};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// Conditions a sample has to meet to be kept, all of the given ones.
+#[derive(clap::Args, Default)]
+pub struct SampleFilter {
+    /// Keep the samples of this session, can be repeated
+    #[arg(long = "session", value_name = "NAME")]
+    pub sessions: Vec<String>,
+    /// Keep the samples with this label, can be repeated
+    #[arg(long = "label", value_name = "LABEL")]
+    pub labels: Vec<String>,
+    /// Keep the samples recorded at or after this Unix time, in seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub since: Option<i64>,
+    /// Keep the samples recorded before this Unix time, in seconds
+    #[arg(long, value_name = "SECONDS")]
+    pub until: Option<i64>,
+    /// Keep the samples whose syscall activity is above this, 0 drops the idle all-zero windows
+    #[arg(long, value_name = "ACTIVITY")]
+    pub min_activity: Option<f64>,
+}
+
+impl SampleFilter {
+    /// A predicate keeping the samples meeting the conditions, to be called on every sample in recording order.
+    pub fn matcher(&self) -> impl FnMut(&StoredSample) -> bool + '_ {
+        let mut activity = Activity::default();
+        move |sample| {
+            //Called first so the counters of every sample are seen.
+            let active = self.min_activity.is_none_or(|min| activity.of(sample).is_none_or(|activity| activity > min));
+            let seconds = |ns: i64| ns.div_euclid(1_000_000_000);
+            let timestamp = sample.timestamp_ns.map(seconds);
+            active
+                && (self.sessions.is_empty() || sample.session.as_ref().is_some_and(|session| self.sessions.contains(session)))
+                && (self.labels.is_empty() || sample.label.as_ref().is_some_and(|label| self.labels.contains(label)))
+                && self.since.is_none_or(|since| timestamp.is_some_and(|timestamp| timestamp >= since))
+                && self.until.is_none_or(|until| timestamp.is_some_and(|timestamp| timestamp < until))
+        }
+    }
+}
+
+//Total activity of a sample: the sum of its features, or of the counter increments since the
+//previous sample of its session when it was recorded without features.
+#[derive(Default)]
+struct Activity {
+    last_counts: HashMap<Option<i64>, Vec<u64>>,
+}
+
+impl Activity {
+    fn of(&mut self, sample: &StoredSample) -> Option<f64> {
+        let increments = sample.counts.as_ref().and_then(|counts| {
+            let last = self.last_counts.insert(sample.session_id, counts.clone())?;
+            Some(counts.iter().zip(&last).map(|(count, last)| count.saturating_sub(*last) as f64).sum())
+        });
+        match &sample.features {
+            Some(features) => Some(features.iter().map(|feature| *feature as f64).sum()),
+            //The first sample of a session cannot be judged from its counters alone.
+            None => increments,
+        }
+    }
+}
+
+/// Remembers the vectors seen so far, the features or else the raw counters.
+#[derive(Default)]
+pub struct Dedup {
+    seen: HashSet<(bool, Vec<(u16, u64)>)>,
+}
+
+impl Dedup {
+    /// Whether no sample with the same vector was seen before.
+    pub fn first(&mut self, sample: &StoredSample) -> bool {
+        //Non-zero entries only, most of them are zero.
+        let key = match (&sample.features, &sample.counts) {
+            (Some(features), _) => (true, sparse(features.iter().map(|feature| feature.to_bits() as u64))),
+            (None, Some(counts)) => (false, sparse(counts.iter().copied())),
+            (None, None) => return true,
+        };
+        self.seen.insert(key)
+    }
+}
+
+fn sparse(values: impl Iterator<Item = u64>) -> Vec<(u16, u64)> {
+    values.enumerate().filter(|(_, value)| *value != 0).map(|(idx, value)| (idx as u16, value)).collect()
+}
+
+/// Pick `per_session` random samples of every session, by default as many as the smallest session has.
+pub fn balance(db: &EbsentinelDb, per_session: Option<usize>, seed: u64) -> anyhow::Result<HashSet<i64>> {
+    let mut sessions: HashMap<Option<i64>, Vec<i64>> = HashMap::new();
+    db.for_each_sample(|sample| {
+        sessions.entry(sample.session_id).or_default().push(sample.sample_id);
+        Ok(())
+    })?;
+    let per_session = per_session.unwrap_or_else(|| sessions.values().map(Vec::len).min().unwrap_or_default());
+
+    //Sessions in a fixed order, so the seed alone decides the picks.
+    let mut sessions: Vec<_> = sessions.into_iter().collect();
+    sessions.sort();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut picked = HashSet::new();
+    for (_, mut samples) in sessions {
+        samples.shuffle(&mut rng);
+        picked.extend(samples.into_iter().take(per_session));
+    }
+    Ok(picked)
+}
+
+#[cfg(test)]
+mod test {
+    use ebsentinel_db::StoredSample;
+
+    use super::{Dedup, SampleFilter};
+
+    fn sample(session_id: i64, timestamp_ns: i64, features: Option<Vec<f32>>, counts: Option<Vec<u64>>) -> StoredSample {
+        StoredSample {
+            sample_id: timestamp_ns,
+            split: None,
+            session_id: Some(session_id),
+            session: Some(format!("s{session_id}")),
+            label: Some("benign".to_string()),
+            pid: Some(1),
+            timestamp_ns: Some(timestamp_ns),
+            features,
+            counts,
+        }
+    }
+
+    #[test]
+    fn drops_idle_windows() {
+        let filter = SampleFilter {
+            min_activity: Some(0.0),
+            ..SampleFilter::default()
+        };
+        let mut keep = filter.matcher();
+        assert!(keep(&sample(1, 1, Some(vec![0.0, 0.5]), None)));
+        assert!(!keep(&sample(1, 2, Some(vec![0.0, 0.0]), None)));
+        //Raw counters are judged by their increments.
+        assert!(keep(&sample(2, 3, None, Some(vec![4, 4]))));
+        assert!(!keep(&sample(2, 4, None, Some(vec![4, 4]))));
+        assert!(keep(&sample(2, 5, None, Some(vec![5, 4]))));
+    }
+
+    #[test]
+    fn filters_by_session_label_and_time() {
+        let filter = SampleFilter {
+            sessions: vec!["s1".to_string()],
+            labels: vec!["benign".to_string()],
+            since: Some(1),
+            until: Some(2),
+            ..SampleFilter::default()
+        };
+        let mut keep = filter.matcher();
+        assert!(keep(&sample(1, 1_500_000_000, None, None)));
+        assert!(!keep(&sample(1, 2_000_000_000, None, None)));
+        assert!(!keep(&sample(2, 1_500_000_000, None, None)));
+    }
+
+    #[test]
+    fn dedup_keeps_first_vector() {
+        let mut dedup = Dedup::default();
+        assert!(dedup.first(&sample(1, 1, Some(vec![0.0, 0.5]), None)));
+        assert!(!dedup.first(&sample(2, 2, Some(vec![0.0, 0.5]), Some(vec![1, 2]))));
+        assert!(dedup.first(&sample(1, 3, None, Some(vec![0, 0]))));
+        assert!(dedup.first(&sample(1, 4, Some(vec![0.0, 0.0]), None)));
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::bail;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::schema::{DENSE_LAYOUT, SPARSE_LAYOUT};
+
+//Non-zero entries of a vector, with the length of the dense vector.
+#[derive(Serialize, Deserialize)]
+struct Sparse<T> {
+    len: u32,
+    entries: Vec<(u16, T)>,
+}
+
+/// Encode a feature or counter vector in `layout`.
+pub fn encode<T>(layout: &str, values: &[T]) -> anyhow::Result<Vec<u8>>
+where
+    T: Serialize + Default + PartialEq + Copy,
+{
+    let bytes = match layout {
+        DENSE_LAYOUT => bincode::serialize(values)?,
+        SPARSE_LAYOUT => {
+            let zero = T::default();
+            let entries = values
+                .iter()
+                .enumerate()
+                .filter(|(_, value)| **value != zero)
+                .map(|(idx, value)| (idx as u16, *value))
+                .collect();
+            bincode::serialize(&Sparse {
+                len: values.len() as u32,
+                entries,
+            })?
+        }
+        _ => bail!("unknown feature layout {layout}"),
+    };
+    Ok(bytes)
+}
+
+/// Decode a vector encoded in `layout` into its dense form.
+pub fn decode<T>(layout: &str, bytes: &[u8]) -> anyhow::Result<Vec<T>>
+where
+    T: DeserializeOwned + Default + Copy,
+{
+    let values = match layout {
+        DENSE_LAYOUT => bincode::deserialize(bytes)?,
+        SPARSE_LAYOUT => {
+            let sparse: Sparse<T> = bincode::deserialize(bytes)?;
+            let mut values = vec![T::default(); sparse.len as usize];
+            for (idx, value) in sparse.entries {
+                match values.get_mut(idx as usize) {
+                    Some(slot) => *slot = value,
+                    None => bail!("sparse entry {idx} out of a vector of {}", sparse.len),
+                }
+            }
+            values
+        }
+        _ => bail!("unknown feature layout {layout}"),
+    };
+    Ok(values)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode, encode};
+    use crate::{schema::SPARSE_LAYOUT, Syscalls, DENSE_LAYOUT};
+
+    #[test]
+    fn sparse_round_trip() {
+        let mut features = vec![0.0f32; 512];
+        features[1] = 0.5;
+        features[257] = 1.0;
+        let bytes = encode(SPARSE_LAYOUT, &features).unwrap();
+        assert!(bytes.len() < 32);
+        assert_eq!(decode::<f32>(SPARSE_LAYOUT, &bytes).unwrap(), features);
+
+        let counts = vec![0u64, 7, 0, u64::MAX];
+        let bytes = encode(SPARSE_LAYOUT, &counts).unwrap();
+        assert_eq!(decode::<u64>(SPARSE_LAYOUT, &bytes).unwrap(), counts);
+    }
+
+    #[test]
+    fn dense_matches_recorded_rows() {
+        let features = vec![0.0f32, 0.25, 1.0];
+        let recorded = bincode::serialize(&Syscalls::new(features.clone())).unwrap();
+        assert_eq!(encode(DENSE_LAYOUT, &features).unwrap(), recorded);
+        assert_eq!(decode::<f32>(DENSE_LAYOUT, &recorded).unwrap(), features);
+    }
+}
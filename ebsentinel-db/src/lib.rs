@@ -0,0 +1,588 @@
+use std::{collections::HashMap, time::Duration};
+
+use anyhow::bail;
+use ebsentinel_features::pipeline::{Pipeline, Preprocessing};
+use rusqlite::{params, types::{FromSql, FromSqlResult, ToSqlOutput, ValueRef}, Connection, OptionalExtension, ToSql, Transaction};
+use serde::{Deserialize, Serialize};
+pub use encoding::{decode, encode};
+pub use schema::{Metadata, DENSE_LAYOUT, RATE_MAX_NORM, SCHEMA_VERSION, SPARSE_LAYOUT};
+pub use split::{Ratios, SplitConfig, SplitStrategy, SPLITS};
+use split::SampleRef;
+mod encoding;
+mod schema;
+mod split;
+
+
+#[derive(Debug, Clone,Serialize,Deserialize)]
+pub struct Syscalls{
+    pub syscalls: Vec<f32>
+}
+
+impl Syscalls {
+    pub fn new(rates: Vec<f32>) -> Self{
+        Self { syscalls: rates }
+    }
+}
+// Custom implementation for `Vec<f32>` serialization to SQLite BLOB, in the dense layout
+impl ToSql for Syscalls {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        let serialized = bincode::serialize(self).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        Ok(ToSqlOutput::from(serialized))
+    }
+}
+
+/// Custom implementation for `Vec<f32>` deserialization from SQLite BLOB
+impl FromSql for Syscalls {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_blob() {
+            Ok(blob) => bincode::deserialize(blob).map_err(|e| rusqlite::types::FromSqlError::Other(Box::new(e))),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Cumulative syscall counters of a monitored process, indexed by syscall number.
+#[derive(Debug, Clone,Serialize,Deserialize)]
+pub struct Counts{
+    pub counts: Vec<u64>
+}
+
+impl Counts {
+    pub fn new(counts: Vec<u64>) -> Self{
+        Self { counts }
+    }
+}
+
+/// Where and how a recording session was made.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub name: String,
+    /// Label of the rows of the session, e.g. `benign` or `attack`.
+    pub label: Option<String>,
+    pub tags: Vec<String>,
+    pub host: String,
+    pub pid: u32,
+    pub binary: Option<String>,
+    /// SHA-256 of the monitored binary, identifies its version.
+    pub binary_sha256: Option<String>,
+    pub kernel: String,
+    pub polling_rate_ms: u64,
+    pub recorder_version: String,
+    /// Unix time in nanoseconds.
+    pub started_at: i64,
+}
+
+/// One recorded sample.
+#[derive(Debug, Clone)]
+pub struct Row {
+    pub session_id: i64,
+    pub pid: u32,
+    /// Unix time in nanoseconds.
+    pub timestamp_ns: i64,
+    pub label: Option<String>,
+    /// Features preprocessed as described by [`Metadata::preprocessing`].
+    pub syscalls: Option<Syscalls>,
+    /// Raw counters the features can be derived again from.
+    pub counts: Option<Counts>,
+    /// One of [`SPLITS`] to add the sample to, None to leave it to the next split.
+    pub split: Option<&'static str>,
+}
+
+/// A recorded sample with the names of its session and split.
+#[derive(Debug, Clone)]
+pub struct StoredSample {
+    pub sample_id: i64,
+    pub split: Option<&'static str>,
+    pub session_id: Option<i64>,
+    pub session: Option<String>,
+    pub label: Option<String>,
+    pub pid: Option<u32>,
+    pub timestamp_ns: Option<i64>,
+    pub features: Option<Vec<f32>>,
+    pub counts: Option<Vec<u64>>,
+}
+
+/// A recorded session read back, with its id in its database.
+#[derive(Debug, Clone)]
+pub struct StoredSession {
+    pub session_id: i64,
+    pub session: Session,
+    pub ended_at: Option<i64>,
+}
+
+/// Samples written in a single transaction.
+pub const BATCH_SIZE: usize = 256;
+//Bounds the memory held while writes keep failing, e.g. on a full disk.
+const MAX_PENDING: usize = 64 * 1024;
+
+pub struct EbsentinelDb{
+    conn: Connection,
+    //Layout of the features and counters of this database.
+    layout: String,
+    //Samples waiting for the next flush.
+    pending: Vec<Row>,
+}
+
+impl EbsentinelDb {
+    /// Open or create the database at `path`, migrating it to the current schema.
+    pub fn new(path: &str) -> anyhow::Result<Self>{
+        let mut conn = Connection::open(path)?;
+        //Readers such as ebsentinel-train do not block the recorder and commits do not wait for fsync.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        schema::migrate(&mut conn)?;
+        let layout = Metadata::load(&conn)?.feature_layout;
+        Ok(Self { conn, layout, pending: Vec::new() })
+    }
+
+    /// How the samples of this database were produced.
+    pub fn metadata(&self) -> anyhow::Result<Metadata>{
+        Metadata::load(&self.conn)
+    }
+
+    /// Fail if samples described by `expected` cannot be mixed with the ones already recorded.
+    ///
+    /// The layout does not matter, new samples are stored in the one of the database.
+    pub fn check_compatible(&self, expected: &Metadata) -> anyhow::Result<()>{
+        let metadata = Metadata {
+            feature_layout: expected.feature_layout.clone(),
+            ..self.metadata()?
+        };
+        if metadata != *expected {
+            bail!("database holds {metadata:?} samples, expected {expected:?}");
+        }
+        Ok(())
+    }
+
+    /// Take over how the samples of another database were produced, before any sample is added.
+    ///
+    /// The layout stays the one of this database.
+    pub fn inherit_metadata(&mut self, source: &Metadata) -> anyhow::Result<()>{
+        if self.samples()? > 0 || !self.pending.is_empty() {
+            bail!("cannot change the metadata of a database holding samples");
+        }
+        let metadata = Metadata {
+            feature_layout: self.layout.clone(),
+            ..source.clone()
+        };
+        let tx = self.conn.transaction()?;
+        metadata.save(&tx)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Register a new recording session and return its id.
+    pub fn start_session(&self, session: &Session) -> anyhow::Result<i64> {
+        self.conn.execute(
+            "INSERT INTO sessions (name, label, host, pid, binary, binary_sha256, kernel, polling_rate_ms, recorder_version, started_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                session.name,
+                session.label,
+                session.host,
+                session.pid,
+                session.binary,
+                session.binary_sha256,
+                session.kernel,
+                session.polling_rate_ms,
+                session.recorder_version,
+                session.started_at,
+            ],
+        )?;
+        let session_id = self.conn.last_insert_rowid();
+        for tag in &session.tags {
+            self.conn.execute("INSERT OR IGNORE INTO session_tags (session_id, tag) VALUES (?, ?)", params![session_id, tag])?;
+        }
+        Ok(session_id)
+    }
+
+    pub fn end_session(&self, session_id: i64, ended_at: i64) -> anyhow::Result<()>{
+        self.conn.execute("UPDATE sessions SET ended_at = ? WHERE session_id = ?", params![ended_at, session_id])?;
+        Ok(())
+    }
+
+    /// Every recorded session with its tags, in recording order.
+    pub fn sessions(&self) -> anyhow::Result<Vec<StoredSession>>{
+        let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+        let mut stmt = self.conn.prepare("SELECT session_id, tag FROM session_tags ORDER BY tag")?;
+        for tag in stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))? {
+            let (session_id, tag) = tag?;
+            tags.entry(session_id).or_default().push(tag);
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT session_id, name, label, host, pid, binary, binary_sha256, kernel, polling_rate_ms, recorder_version, started_at, ended_at
+             FROM sessions ORDER BY session_id",
+        )?;
+        let sessions = stmt
+            .query_map([], |row| {
+                let session_id: i64 = row.get(0)?;
+                Ok(StoredSession {
+                    session_id,
+                    session: Session {
+                        name: row.get(1)?,
+                        label: row.get(2)?,
+                        tags: tags.remove(&session_id).unwrap_or_default(),
+                        host: row.get(3)?,
+                        pid: row.get(4)?,
+                        binary: row.get(5)?,
+                        binary_sha256: row.get(6)?,
+                        kernel: row.get(7)?,
+                        polling_rate_ms: row.get(8)?,
+                        recorder_version: row.get(9)?,
+                        started_at: row.get(10)?,
+                    },
+                    ended_at: row.get(11)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(sessions)
+    }
+
+    /// Buffer a sample, every [`BATCH_SIZE`] samples the buffer is flushed.
+    ///
+    /// Unless it names its split, it joins one at the next [`EbsentinelDb::split_new`]. A failed flush keeps the samples for the
+    /// next one, until too many are buffered and new samples are dropped.
+    pub fn add_sample(&mut self, row: Row) -> anyhow::Result<()>{
+        if self.pending.len() >= MAX_PENDING {
+            bail!("{} samples are waiting to be written, sample dropped", self.pending.len());
+        }
+        if let Some(split) = row.split.filter(|split| !SPLITS.contains(split)) {
+            bail!("unknown split {split}, expected one of {}", SPLITS.join(", "));
+        }
+        self.pending.push(row);
+        if self.pending.len().is_multiple_of(BATCH_SIZE) {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Number of samples written, not counting the ones waiting for the next flush.
+    pub fn samples(&self) -> anyhow::Result<usize>{
+        let samples: i64 = self.conn.query_row("SELECT count(*) FROM samples", [], |row| row.get(0))?;
+        Ok(samples as usize)
+    }
+
+    /// Number of samples in each of [`SPLITS`].
+    pub fn split_sizes(&self) -> anyhow::Result<[usize; 3]>{
+        let mut sizes = [0; 3];
+        for (size, table) in sizes.iter_mut().zip(SPLITS) {
+            let count: i64 = self.conn.query_row(&format!("SELECT count(*) FROM {table}_samples"), [], |row| row.get(0))?;
+            *size = count as usize;
+        }
+        Ok(sizes)
+    }
+
+    /// Number of samples waiting for the next flush.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Write the buffered samples in a single transaction and return how many were written.
+    pub fn flush(&mut self) -> anyhow::Result<usize>{
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+        let tx = self.conn.transaction()?;
+        {
+            let mut insert = tx.prepare_cached(
+                "INSERT INTO samples (syscalls, counts, session_id, pid, timestamp_ns, label) VALUES (?, ?, ?, ?, ?, ?)",
+            )?;
+            for row in &self.pending {
+                let syscalls = row.syscalls.as_ref().map(|syscalls| encode(&self.layout, &syscalls.syscalls)).transpose()?;
+                let counts = row.counts.as_ref().map(|counts| encode(&self.layout, &counts.counts)).transpose()?;
+                insert.execute(params![syscalls, counts, row.session_id, row.pid, row.timestamp_ns, row.label])?;
+                if let Some(table) = row.split {
+                    let sample_id = tx.last_insert_rowid();
+                    tx.prepare_cached(&format!(
+                        "INSERT INTO {table}_samples (row_id, sample_id) SELECT count(*) + 1, ? FROM {table}_samples"
+                    ))?
+                    .execute([sample_id])?;
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(std::mem::take(&mut self.pending).len())
+    }
+
+    /// The configuration of the last split, if the samples were ever split.
+    pub fn split_config(&self) -> anyhow::Result<Option<SplitConfig>>{
+        let get = |key: &str| -> rusqlite::Result<Option<String>> {
+            self.conn.query_row("SELECT value FROM metadata WHERE key = ?", [key], |row| row.get(0)).optional()
+        };
+        let (Some(strategy), Some(ratios), Some(seed)) = (get("split_strategy")?, get("split_ratios")?, get("split_seed")?) else {
+            return Ok(None);
+        };
+        Ok(Some(SplitConfig {
+            strategy: strategy.parse()?,
+            ratios: ratios.parse()?,
+            seed: seed.parse()?,
+        }))
+    }
+
+    /// Rebuild the train, valid and test splits from all the samples and return their sizes.
+    ///
+    /// Samples may move to another split, models trained before on this database should not be evaluated on it anymore.
+    pub fn split(&mut self, config: &SplitConfig) -> anyhow::Result<[usize; 3]>{
+        self.flush()?;
+        let tx = self.conn.transaction()?;
+        for table in SPLITS {
+            tx.execute(&format!("DELETE FROM {table}_samples"), [])?;
+        }
+        let sizes = assign_splits(&tx, "", config, [0; 3])?;
+        tx.commit()?;
+        Ok(sizes)
+    }
+
+    /// Assign the samples that are in no split yet, the others stay where they are, and return the sizes of the splits.
+    pub fn split_new(&mut self, config: &SplitConfig) -> anyhow::Result<[usize; 3]>{
+        self.flush()?;
+        let existing = self.split_sizes()?;
+        let tx = self.conn.transaction()?;
+        let unsplit = SPLITS.map(|table| format!("row_id NOT IN (SELECT sample_id FROM {table}_samples)")).join(" AND ");
+        let sizes = assign_splits(&tx, &format!("WHERE {unsplit}"), config, existing)?;
+        tx.commit()?;
+        Ok(sizes)
+    }
+
+    /// Store the features and counters of every sample in `layout` and return how many vectors were rewritten.
+    pub fn convert(&mut self, layout: &str) -> anyhow::Result<usize>{
+        self.flush()?;
+        let tx = self.conn.transaction()?;
+        let mut converted = 0;
+        {
+            let rows = tx
+                .prepare("SELECT row_id, syscalls, counts FROM samples")?
+                .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Option<Vec<u8>>>(1)?, row.get::<_, Option<Vec<u8>>>(2)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut update = tx.prepare("UPDATE samples SET syscalls = ?, counts = ? WHERE row_id = ?")?;
+            for (row_id, syscalls, counts) in rows {
+                let syscalls = syscalls
+                    .map(|bytes| encode(layout, &decode::<f32>(&self.layout, &bytes)?))
+                    .transpose()?;
+                let counts = counts
+                    .map(|bytes| encode(layout, &decode::<u64>(&self.layout, &bytes)?))
+                    .transpose()?;
+                converted += syscalls.is_some() as usize + counts.is_some() as usize;
+                update.execute(params![syscalls, counts, row_id])?;
+            }
+        }
+        let metadata = Metadata {
+            feature_layout: layout.to_string(),
+            ..Metadata::load(&tx)?
+        };
+        metadata.save(&tx)?;
+        tx.commit()?;
+        self.layout = layout.to_string();
+        //Give the freed pages back to the file system.
+        self.conn.execute("VACUUM", [])?;
+        Ok(converted)
+    }
+
+    /// Size of the database file in bytes.
+    pub fn size(&self) -> anyhow::Result<u64>{
+        let pages: u64 = self.conn.pragma_query_value(None, "page_count", |row| row.get(0))?;
+        let page_size: u64 = self.conn.pragma_query_value(None, "page_size", |row| row.get(0))?;
+        Ok(pages * page_size)
+    }
+
+    /// Call `f` on every sample, in recording order, with its vectors decoded.
+    pub fn for_each_sample(&self, mut f: impl FnMut(StoredSample) -> anyhow::Result<()>) -> anyhow::Result<()>{
+        let mut splits = HashMap::new();
+        for table in SPLITS {
+            let mut stmt = self.conn.prepare(&format!("SELECT sample_id FROM {table}_samples"))?;
+            for sample_id in stmt.query_map([], |row| row.get::<_, i64>(0))? {
+                splits.insert(sample_id?, table);
+            }
+        }
+
+        let mut stmt = self.conn.prepare(
+            "SELECT samples.row_id, samples.session_id, sessions.name, samples.label, samples.pid, samples.timestamp_ns, samples.syscalls, samples.counts
+             FROM samples LEFT JOIN sessions ON sessions.session_id = samples.session_id
+             ORDER BY samples.row_id",
+        )?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let sample_id: i64 = row.get(0)?;
+            let features: Option<Vec<u8>> = row.get(6)?;
+            let counts: Option<Vec<u8>> = row.get(7)?;
+            f(StoredSample {
+                sample_id,
+                split: splits.get(&sample_id).copied(),
+                session_id: row.get(1)?,
+                session: row.get(2)?,
+                label: row.get(3)?,
+                pid: row.get(4)?,
+                timestamp_ns: row.get(5)?,
+                features: features.map(|bytes| decode(&self.layout, &bytes)).transpose()?,
+                counts: counts.map(|bytes| decode(&self.layout, &bytes)).transpose()?,
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Number of rows of `table` without stored features, they can only be used through [`EbsentinelDb::derive_features`].
+    pub fn missing_features(&self, table: &str) -> anyhow::Result<usize>{
+        let missing: i64 = self.conn.query_row(&format!("SELECT count(*) FROM {table} WHERE syscalls IS NULL"), [], |row| row.get(0))?;
+        Ok(missing as usize)
+    }
+
+    /// Derive the features of the rows of `table` from their raw counters, in row order.
+    ///
+    /// Every session goes through its own [`Pipeline`] in time order, whatever split its samples
+    /// are in. Rows without raw counters, and the first `window` rows of every session, are skipped.
+    pub fn derive_features(&self, table: &str, preprocessing: &Preprocessing) -> anyhow::Result<Vec<Vec<f32>>>{
+        let polling_rates = self
+            .conn
+            .prepare("SELECT session_id, polling_rate_ms FROM sessions")?
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, u64>(1)?)))?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        let mut features = HashMap::new();
+        let mut stmt = self.conn.prepare(
+            "SELECT row_id, session_id, timestamp_ns, counts FROM samples
+             WHERE counts IS NOT NULL ORDER BY session_id, timestamp_ns, row_id",
+        )?;
+        let mut rows = stmt.query([])?;
+        let mut current: Option<(Option<i64>, Pipeline)> = None;
+        while let Some(row) = rows.next()? {
+            let (row_id, session_id): (i64, Option<i64>) = (row.get(0)?, row.get(1)?);
+            let (timestamp_ns, counts): (Option<i64>, Vec<u8>) = (row.get(2)?, row.get(3)?);
+            let counts: Vec<u64> = decode(&self.layout, &counts)?;
+            if current.as_ref().is_none_or(|(session, _)| *session != session_id) {
+                //Raw counters are only recorded along a session, 100 ms is the polling rate of ebsentinel-rec.
+                let polling_rate_ms = session_id.and_then(|id| polling_rates.get(&id)).copied().unwrap_or(100);
+                let pipeline = Pipeline::new(preprocessing.clone(), Duration::from_millis(polling_rate_ms));
+                current = Some((session_id, pipeline));
+            }
+            let (_, pipeline) = current.as_mut().unwrap();
+            if let Some(sample) = pipeline.push(timestamp_ns.unwrap_or_default(), &counts) {
+                features.insert(row_id, sample);
+            }
+        }
+
+        let sample_ids = self
+            .conn
+            .prepare(&format!("SELECT sample_id FROM {table} ORDER BY row_id"))?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(sample_ids.into_iter().filter_map(|id| features.remove(&id)).collect())
+    }
+}
+
+//Assign the samples matching `filter` to the splits, which already hold `existing` samples, and save `config`.
+fn assign_splits(tx: &Transaction, filter: &str, config: &SplitConfig, existing: [usize; 3]) -> anyhow::Result<[usize; 3]>{
+    let samples = tx
+        .prepare(&format!("SELECT row_id, session_id FROM samples {filter} ORDER BY session_id, timestamp_ns, row_id"))?
+        .query_map([], |row| Ok(SampleRef { row_id: row.get(0)?, session_id: row.get(1)? }))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let splits = split::assign(&samples, config, existing);
+
+    //Row ids of a split go from 1 to its size, new samples are appended.
+    for ((table, sample_ids), offset) in SPLITS.iter().zip(&splits).zip(existing) {
+        let mut insert = tx.prepare(&format!("INSERT INTO {table}_samples (row_id, sample_id) VALUES (?, ?)"))?;
+        for (i, sample_id) in sample_ids.iter().enumerate() {
+            insert.execute(params![(offset + i) as i64 + 1, sample_id])?;
+        }
+    }
+    let entries = [
+        ("split_strategy", config.strategy.to_string()),
+        ("split_ratios", config.ratios.to_string()),
+        ("split_seed", config.seed.to_string()),
+    ];
+    for (key, value) in entries {
+        tx.execute("INSERT OR REPLACE INTO metadata (key, value) VALUES (?, ?)", params![key, value])?;
+    }
+    let mut sizes = existing;
+    for (size, sample_ids) in sizes.iter_mut().zip(&splits) {
+        *size += sample_ids.len();
+    }
+    Ok(sizes)
+}
+
+impl Drop for EbsentinelDb {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            eprintln!("{} samples lost: {e:#}", self.pending.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ebsentinel_features::pipeline::{Preprocessing, Rate, Scaler};
+
+    use super::{Counts, EbsentinelDb, Ratios, Row, Session, SplitConfig, SplitStrategy, Syscalls, DENSE_LAYOUT};
+
+    #[test]
+    fn derives_features_from_raw_counters() {
+        let mut db = EbsentinelDb::new(":memory:").unwrap();
+        let session = Session {
+            name: "test".to_string(),
+            label: None,
+            tags: Vec::new(),
+            host: "host".to_string(),
+            pid: 1,
+            binary: None,
+            binary_sha256: None,
+            kernel: "6.1".to_string(),
+            polling_rate_ms: 100,
+            recorder_version: "0".to_string(),
+            started_at: 0,
+        };
+        let session_id = db.start_session(&session).unwrap();
+        for i in 0..10u64 {
+            let row = Row {
+                session_id,
+                pid: 1,
+                timestamp_ns: i as i64 * 200_000_000,
+                label: None,
+                syscalls: (i % 2 == 0).then(|| Syscalls::new(vec![0.0; 2])),
+                counts: Some(Counts::new(vec![i * i, i])),
+                split: None,
+            };
+            db.add_sample(row).unwrap();
+        }
+        let config = SplitConfig {
+            strategy: SplitStrategy::Contiguous,
+            ratios: Ratios([0.5, 0.5, 0.0]),
+            seed: 0,
+        };
+        assert_eq!(db.split(&config).unwrap(), [5, 5, 0]);
+        assert_eq!(db.missing_features("train").unwrap(), 2);
+
+        let preprocessing = Preprocessing {
+            rate: Rate::Elapsed,
+            window: 1,
+            scaler: Scaler::None,
+        };
+        //The first sample has no predecessor, the others are 200 ms apart.
+        let train = db.derive_features("train", &preprocessing).unwrap();
+        assert_eq!(train, vec![vec![5.0, 5.0], vec![15.0, 5.0], vec![25.0, 5.0], vec![35.0, 5.0]]);
+        let valid = db.derive_features("valid", &preprocessing).unwrap();
+        assert_eq!(valid.len(), 5);
+        assert_eq!(valid[0], vec![45.0, 5.0]);
+
+        //Samples added later join the splits, the ones already split stay where they are.
+        for (i, split) in [(10u64, None), (11, None), (12, Some("test"))] {
+            let row = Row {
+                session_id,
+                pid: 1,
+                timestamp_ns: i as i64 * 200_000_000,
+                label: None,
+                syscalls: None,
+                counts: Some(Counts::new(vec![i * i, i])),
+                split,
+            };
+            db.add_sample(row).unwrap();
+        }
+        assert_eq!(db.split_new(&config).unwrap(), [6, 6, 1]);
+        assert_eq!(db.derive_features("train", &preprocessing).unwrap()[..4], train);
+
+        //Every stored vector is rewritten once, the splits only hold sample ids.
+        //Counters of every sample, features of the even ones.
+        assert_eq!(db.convert(DENSE_LAYOUT).unwrap(), 13 + 5);
+        assert_eq!(db.metadata().unwrap().feature_layout, DENSE_LAYOUT);
+        assert_eq!(db.derive_features("train", &preprocessing).unwrap()[..4], train);
+    }
+}
@@ -0,0 +1,378 @@
+use anyhow::{anyhow, bail};
+use ebsentinel_common::MAX_SYSCALLS;
+use ebsentinel_features::syscall_table_hash;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+
+use crate::split::SPLITS;
+
+/// Version of the layout created by this build, stored in `PRAGMA user_version`.
+pub const SCHEMA_VERSION: u32 = 6;
+
+/// Dense vectors of MAX_SYSCALLS values, indexed by syscall number.
+pub const DENSE_LAYOUT: &str = "dense-f32";
+/// Only the non-zero (syscall number, value) pairs of the vectors, the layout of new databases.
+pub const SPARSE_LAYOUT: &str = "sparse-f32";
+/// Syscall counts differentiated into rates, then divided by the largest rate of the sample.
+pub const RATE_MAX_NORM: &str = "rate,max-norm";
+
+//MIGRATIONS[n] migrates a database from version n to n + 1.
+const MIGRATIONS: [fn(&Transaction) -> rusqlite::Result<()>; SCHEMA_VERSION as usize] =
+    [create_splits, add_sessions, add_metadata, add_samples, add_raw_counts, index_splits];
+
+/// How the samples of a database were produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub feature_layout: String,
+    pub feature_size: usize,
+    pub preprocessing: String,
+    pub arch: String,
+    /// SHA-256 of the syscall names of `arch`, two hosts agree on syscall numbers if it matches.
+    pub syscall_table_hash: String,
+}
+
+impl Metadata {
+    /// What this build records.
+    pub fn current() -> Self {
+        Self {
+            feature_layout: SPARSE_LAYOUT.to_string(),
+            feature_size: MAX_SYSCALLS as usize,
+            preprocessing: RATE_MAX_NORM.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            syscall_table_hash: syscall_table_hash(),
+        }
+    }
+
+    pub(crate) fn load(conn: &Connection) -> anyhow::Result<Self> {
+        let get = |key: &str| -> anyhow::Result<String> {
+            conn.query_row("SELECT value FROM metadata WHERE key = ?", [key], |row| row.get(0))
+                .optional()?
+                .ok_or_else(|| anyhow!("metadata {key} is missing"))
+        };
+        Ok(Self {
+            feature_layout: get("feature_layout")?,
+            feature_size: get("feature_size")?.parse()?,
+            preprocessing: get("preprocessing")?,
+            arch: get("arch")?,
+            syscall_table_hash: get("syscall_table_hash")?,
+        })
+    }
+
+    pub(crate) fn save(&self, tx: &Transaction) -> rusqlite::Result<()> {
+        let entries = [
+            ("feature_layout", self.feature_layout.clone()),
+            ("feature_size", self.feature_size.to_string()),
+            ("preprocessing", self.preprocessing.clone()),
+            ("arch", self.arch.clone()),
+            ("syscall_table_hash", self.syscall_table_hash.clone()),
+        ];
+        for (key, value) in entries {
+            tx.execute("INSERT OR REPLACE INTO metadata (key, value) VALUES (?, ?)", params![key, value])?;
+        }
+        Ok(())
+    }
+}
+
+/// Bring the database up to [`SCHEMA_VERSION`], one transaction per migration.
+pub(crate) fn migrate(conn: &mut Connection) -> anyhow::Result<()> {
+    let mut version = detect_version(conn)?;
+    if version > SCHEMA_VERSION {
+        bail!("database schema version {version} is newer than the supported version {SCHEMA_VERSION}");
+    }
+    let (created, from) = (version == 0, version);
+    while version < SCHEMA_VERSION {
+        let tx = conn.transaction()?;
+        MIGRATIONS[version as usize](&tx)?;
+        version += 1;
+        tx.pragma_update(None, "user_version", version)?;
+        //Migrations describe the samples of existing databases, new ones are recorded the current way.
+        if created && version == SCHEMA_VERSION {
+            Metadata::current().save(&tx)?;
+        }
+        tx.commit()?;
+    }
+    //Splits held copies of their samples until version 6, give their pages back to the file system.
+    if from != 0 && from < 6 {
+        conn.execute("VACUUM", [])?;
+    }
+    Ok(())
+}
+
+fn detect_version(conn: &Connection) -> rusqlite::Result<u32> {
+    let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version != 0 {
+        return Ok(version);
+    }
+    //Databases created before the schema was versioned.
+    if !table_exists(conn, "train")? {
+        Ok(0)
+    } else if column_exists(conn, "train", "session_id")? {
+        Ok(2)
+    } else {
+        Ok(1)
+    }
+}
+
+pub(crate) fn table_exists(conn: &Connection, table: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT count(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [table],
+        |row| row.get::<_, u32>(0),
+    )
+    .map(|count| count > 0)
+}
+
+fn column_exists(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let columns = stmt.query_map([], |row| row.get::<_, String>(1))?.collect::<Result<Vec<_>, _>>()?;
+    Ok(columns.iter().any(|name| name == column))
+}
+
+fn create_splits(tx: &Transaction) -> rusqlite::Result<()> {
+    for table in ["train", "test"] {
+        tx.execute(
+            &format!(
+                "create table if not exists {table} (
+                     row_id integer primary key,
+                     syscalls blob not null
+                 )"
+            ),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+fn add_sessions(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "create table sessions (
+             session_id integer primary key,
+             name text not null,
+             label text,
+             host text not null,
+             pid integer not null,
+             binary text,
+             binary_sha256 text,
+             kernel text not null,
+             polling_rate_ms integer not null,
+             recorder_version text not null,
+             started_at integer not null,
+             ended_at integer
+         )",
+        [],
+    )?;
+    tx.execute(
+        "create table session_tags (
+             session_id integer not null references sessions(session_id),
+             tag text not null,
+             primary key (session_id, tag)
+         )",
+        [],
+    )?;
+    for table in ["train", "test"] {
+        tx.execute(&format!("alter table {table} add column session_id integer references sessions(session_id)"), [])?;
+        tx.execute(&format!("alter table {table} add column pid integer"), [])?;
+        tx.execute(&format!("alter table {table} add column timestamp_ns integer"), [])?;
+        tx.execute(&format!("alter table {table} add column label text"), [])?;
+    }
+    Ok(())
+}
+
+fn add_metadata(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "create table metadata (
+             key text primary key,
+             value text not null
+         )",
+        [],
+    )?;
+    //Every earlier version recorded the same dense layout, only the architecture is a guess.
+    Metadata {
+        feature_layout: DENSE_LAYOUT.to_string(),
+        ..Metadata::current()
+    }
+    .save(tx)
+}
+
+fn add_samples(tx: &Transaction) -> rusqlite::Result<()> {
+    tx.execute(
+        "create table samples (
+             row_id integer primary key,
+             syscalls blob not null,
+             session_id integer references sessions(session_id),
+             pid integer,
+             timestamp_ns integer,
+             label text
+         )",
+        [],
+    )?;
+    for table in ["train", "test"] {
+        tx.execute(
+            &format!(
+                "INSERT INTO samples (syscalls, session_id, pid, timestamp_ns, label)
+                 SELECT syscalls, session_id, pid, timestamp_ns, label FROM {table} ORDER BY row_id"
+            ),
+            [],
+        )?;
+    }
+    let recorded_train: i64 = tx.query_row("SELECT count(*) FROM train", [], |row| row.get(0))?;
+    tx.execute("drop table train", [])?;
+    tx.execute("drop table test", [])?;
+    //Splits are copies of their samples: burn reads a table as a dataset only if its row_id go from 1 to its length.
+    for table in SPLITS {
+        tx.execute(
+            &format!(
+                "create table {table} (
+                     row_id integer primary key,
+                     sample_id integer not null references samples(row_id),
+                     syscalls blob not null,
+                     session_id integer references sessions(session_id),
+                     pid integer,
+                     timestamp_ns integer,
+                     label text
+                 )"
+            ),
+            [],
+        )?;
+    }
+    //Rows recorded with -t were only ever used to validate, keep them as the validation split until the next split.
+    tx.execute(
+        "INSERT INTO train SELECT row_id, row_id, syscalls, session_id, pid, timestamp_ns, label FROM samples WHERE row_id <= ?",
+        [recorded_train],
+    )?;
+    tx.execute(
+        "INSERT INTO valid SELECT row_id - ?1, row_id, syscalls, session_id, pid, timestamp_ns, label FROM samples WHERE row_id > ?1",
+        [recorded_train],
+    )?;
+    Ok(())
+}
+
+fn add_raw_counts(tx: &Transaction) -> rusqlite::Result<()> {
+    //Features become optional, SQLite only drops a not null constraint by copying the tables.
+    let tables = ["samples", "train", "valid", "test"];
+    for table in tables {
+        tx.execute(&format!("alter table {table} rename to {table}_v4"), [])?;
+    }
+    tx.execute(
+        "create table samples (
+             row_id integer primary key,
+             syscalls blob,
+             counts blob,
+             session_id integer references sessions(session_id),
+             pid integer,
+             timestamp_ns integer,
+             label text
+         )",
+        [],
+    )?;
+    for table in SPLITS {
+        tx.execute(
+            &format!(
+                "create table {table} (
+                     row_id integer primary key,
+                     sample_id integer not null references samples(row_id),
+                     syscalls blob,
+                     counts blob,
+                     session_id integer references sessions(session_id),
+                     pid integer,
+                     timestamp_ns integer,
+                     label text
+                 )"
+            ),
+            [],
+        )?;
+    }
+    tx.execute(
+        "INSERT INTO samples (row_id, syscalls, session_id, pid, timestamp_ns, label)
+         SELECT row_id, syscalls, session_id, pid, timestamp_ns, label FROM samples_v4",
+        [],
+    )?;
+    for table in SPLITS {
+        tx.execute(
+            &format!(
+                "INSERT INTO {table} (row_id, sample_id, syscalls, session_id, pid, timestamp_ns, label)
+                 SELECT row_id, sample_id, syscalls, session_id, pid, timestamp_ns, label FROM {table}_v4"
+            ),
+            [],
+        )?;
+    }
+    //Splits first, they reference the samples.
+    for table in tables.iter().rev() {
+        tx.execute(&format!("drop table {table}_v4"), [])?;
+    }
+    Ok(())
+}
+
+fn index_splits(tx: &Transaction) -> rusqlite::Result<()> {
+    //Splits become lists of sample ids, read through views with the columns of their samples.
+    for table in SPLITS {
+        tx.execute(
+            &format!(
+                "create table {table}_samples (
+                     row_id integer primary key,
+                     sample_id integer not null unique references samples(row_id)
+                 )"
+            ),
+            [],
+        )?;
+        tx.execute(&format!("INSERT INTO {table}_samples SELECT row_id, sample_id FROM {table}"), [])?;
+        tx.execute(&format!("drop table {table}"), [])?;
+        //burn reads a split as a dataset only if its row_id go from 1 to its length.
+        tx.execute(
+            &format!(
+                "create view {table} as
+                 SELECT split.row_id AS row_id, split.sample_id AS sample_id, samples.syscalls AS syscalls, samples.counts AS counts,
+                        samples.session_id AS session_id, samples.pid AS pid, samples.timestamp_ns AS timestamp_ns, samples.label AS label
+                 FROM {table}_samples AS split JOIN samples ON samples.row_id = split.sample_id"
+            ),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rusqlite::Connection;
+
+    use super::{migrate, Metadata, DENSE_LAYOUT, SCHEMA_VERSION};
+
+    #[test]
+    fn migrates_unversioned_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute("create table train (row_id integer primary key, syscalls blob not null)", []).unwrap();
+        conn.execute("create table test (row_id integer primary key, syscalls blob not null)", []).unwrap();
+        conn.execute("INSERT INTO train (syscalls) VALUES (x'00')", []).unwrap();
+        conn.execute("INSERT INTO test (syscalls) VALUES (x'01')", []).unwrap();
+
+        migrate(&mut conn).unwrap();
+
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        let rows: u32 = conn.query_row("SELECT count(*) FROM samples WHERE session_id IS NULL", [], |row| row.get(0)).unwrap();
+        assert_eq!(rows, 2);
+        let train: Vec<i64> = conn.prepare("SELECT sample_id FROM train").unwrap().query_map([], |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(train, vec![1]);
+        let valid: Vec<(i64, i64)> = conn.prepare("SELECT row_id, sample_id FROM valid").unwrap().query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(valid, vec![(1, 2)]);
+        let metadata = Metadata::load(&conn).unwrap();
+        assert_eq!(metadata.feature_layout, DENSE_LAYOUT);
+        assert_eq!(metadata.syscall_table_hash, Metadata::current().syscall_table_hash);
+
+        //Migrating again is a no-op.
+        migrate(&mut conn).unwrap();
+    }
+
+    #[test]
+    fn creates_sparse_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(Metadata::load(&conn).unwrap(), Metadata::current());
+    }
+
+    #[test]
+    fn refuses_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+}
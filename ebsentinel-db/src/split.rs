@@ -0,0 +1,230 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+/// Tables the samples are split into, in order.
+pub const SPLITS: [&str; 3] = ["train", "valid", "test"];
+
+/// How samples are assigned to the splits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitStrategy {
+    /// Samples are shuffled independently of each other.
+    Random,
+    /// Every session is cut in time order, its oldest samples go to train and its newest to test.
+    Contiguous,
+    /// Whole sessions are shuffled and assigned to a single split.
+    Session,
+}
+
+impl FromStr for SplitStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Self::Random),
+            "contiguous" => Ok(Self::Contiguous),
+            "session" => Ok(Self::Session),
+            _ => Err(anyhow!("unknown split strategy {s}, expected random, contiguous or session")),
+        }
+    }
+}
+
+impl fmt::Display for SplitStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Random => "random",
+            Self::Contiguous => "contiguous",
+            Self::Session => "session",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Ratios of the train, valid and test splits, summing to one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ratios(pub [f64; 3]);
+
+impl FromStr for Ratios {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s.split(',').map(|part| part.trim().parse::<f64>()).collect::<Result<Vec<_>, _>>()?;
+        let Ok(ratios) = <[f64; 3]>::try_from(parts) else {
+            bail!("expected TRAIN,VALID,TEST ratios, got {s}");
+        };
+        let sum: f64 = ratios.iter().sum();
+        if ratios.iter().any(|ratio| *ratio < 0.0) || sum <= 0.0 {
+            bail!("split ratios must be positive, got {s}");
+        }
+        Ok(Self(ratios.map(|ratio| ratio / sum)))
+    }
+}
+
+impl fmt::Display for Ratios {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [train, valid, test] = self.0;
+        write!(f, "{train},{valid},{test}")
+    }
+}
+
+/// How a database is split, stored with it so the split can be reproduced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplitConfig {
+    pub strategy: SplitStrategy,
+    pub ratios: Ratios,
+    pub seed: u64,
+}
+
+impl Default for SplitConfig {
+    fn default() -> Self {
+        Self {
+            strategy: SplitStrategy::Contiguous,
+            ratios: Ratios([0.8, 0.1, 0.1]),
+            seed: 42,
+        }
+    }
+}
+
+/// A sample to assign, listed in session then time order.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SampleRef {
+    pub row_id: i64,
+    pub session_id: Option<i64>,
+}
+
+/// Assign the row ids of `samples` to the train, valid and test splits, which already hold `existing` samples.
+pub(crate) fn assign(samples: &[SampleRef], config: &SplitConfig, existing: [usize; 3]) -> [Vec<i64>; 3] {
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut splits: [Vec<i64>; 3] = Default::default();
+    match config.strategy {
+        SplitStrategy::Random => {
+            let mut shuffled = samples.to_vec();
+            shuffled.shuffle(&mut rng);
+            cut(&shuffled, &config.ratios, &mut splits);
+        }
+        SplitStrategy::Contiguous => {
+            for session in sessions(samples).values() {
+                cut(session, &config.ratios, &mut splits);
+            }
+        }
+        SplitStrategy::Session => {
+            let mut sessions: Vec<Vec<SampleRef>> = sessions(samples).into_values().collect();
+            sessions.shuffle(&mut rng);
+            let total = (existing.iter().sum::<usize>() + samples.len()) as f64;
+            let targets = config.ratios.0.map(|ratio| ratio * total);
+            let mut sizes = existing;
+            for session in sessions {
+                //A session goes to the split missing the largest part of its target, so every split
+                //with a ratio gets a session before any gets a second one.
+                let missing = |split: usize| {
+                    if targets[split] == 0.0 {
+                        f64::NEG_INFINITY
+                    } else {
+                        (targets[split] - sizes[split] as f64) / targets[split]
+                    }
+                };
+                let split = (0..3).max_by(|a, b| missing(*a).total_cmp(&missing(*b)).then(b.cmp(a))).unwrap();
+                sizes[split] += session.len();
+                splits[split].extend(session.iter().map(|sample| sample.row_id));
+            }
+        }
+    }
+    //Keep the recording order inside every split.
+    for split in &mut splits {
+        split.sort_unstable();
+    }
+    splits
+}
+
+fn sessions(samples: &[SampleRef]) -> BTreeMap<Option<i64>, Vec<SampleRef>> {
+    let mut sessions: BTreeMap<Option<i64>, Vec<SampleRef>> = BTreeMap::new();
+    for sample in samples {
+        sessions.entry(sample.session_id).or_default().push(*sample);
+    }
+    sessions
+}
+
+fn cut(samples: &[SampleRef], ratios: &Ratios, splits: &mut [Vec<i64>; 3]) {
+    let [train, valid, _] = ratios.0;
+    let n = samples.len() as f64;
+    let train_end = (n * train).round() as usize;
+    let valid_end = ((n * (train + valid)).round() as usize).max(train_end);
+    splits[0].extend(samples[..train_end].iter().map(|sample| sample.row_id));
+    splits[1].extend(samples[train_end..valid_end].iter().map(|sample| sample.row_id));
+    splits[2].extend(samples[valid_end..].iter().map(|sample| sample.row_id));
+}
+
+#[cfg(test)]
+mod test {
+    use super::{assign, Ratios, SampleRef, SplitConfig, SplitStrategy};
+
+    fn samples() -> Vec<SampleRef> {
+        (1..=100)
+            .map(|row_id| SampleRef {
+                row_id,
+                session_id: Some((row_id - 1) / 25),
+            })
+            .collect()
+    }
+
+    fn config(strategy: SplitStrategy) -> SplitConfig {
+        SplitConfig {
+            strategy,
+            ratios: Ratios([0.6, 0.2, 0.2]),
+            seed: 7,
+        }
+    }
+
+    #[test]
+    fn random_split_is_reproducible() {
+        let samples = samples();
+        let splits = assign(&samples, &config(SplitStrategy::Random), [0; 3]);
+        assert_eq!(splits.each_ref().map(Vec::len), [60, 20, 20]);
+        assert_eq!(splits, assign(&samples, &config(SplitStrategy::Random), [0; 3]));
+
+        let mut other = config(SplitStrategy::Random);
+        other.seed = 8;
+        assert_ne!(splits, assign(&samples, &other, [0; 3]));
+    }
+
+    #[test]
+    fn contiguous_split_keeps_time_order() {
+        let splits = assign(&samples(), &config(SplitStrategy::Contiguous), [0; 3]);
+        //Every session of 25 samples is cut into 15, 5 and 5.
+        assert_eq!(splits[0][..15], (1..=15).collect::<Vec<_>>());
+        assert_eq!(splits[1][..5], (16..=20).collect::<Vec<_>>());
+        assert_eq!(splits[2][..5], (21..=25).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn session_split_keeps_sessions_whole() {
+        let sessions = |split: &Vec<i64>| {
+            let mut sessions: Vec<i64> = split.iter().map(|row_id| (row_id - 1) / 25).collect();
+            sessions.dedup();
+            sessions
+        };
+        let mut config = config(SplitStrategy::Session);
+        for ratios in [[0.6, 0.2, 0.2], [0.8, 0.1, 0.1]] {
+            config.ratios = Ratios(ratios);
+            let splits = assign(&samples(), &config, [0; 3]);
+            assert!(splits.iter().all(|split| !split.is_empty()), "{ratios:?}: {splits:?}");
+            let mut all: Vec<i64> = splits.iter().flat_map(sessions).collect();
+            all.sort_unstable();
+            assert_eq!(all, vec![0, 1, 2, 3]);
+        }
+
+        //A new session goes to the split furthest from its ratio.
+        let new: Vec<SampleRef> = samples().into_iter().filter(|sample| sample.session_id == Some(0)).collect();
+        config.ratios = Ratios([0.6, 0.2, 0.2]);
+        let splits = assign(&new, &config, [75, 0, 25]);
+        assert_eq!(splits.each_ref().map(Vec::len), [0, 25, 0]);
+    }
+
+    #[test]
+    fn parses_ratios() {
+        assert_eq!("8,1,1".parse::<Ratios>().unwrap(), Ratios([0.8, 0.1, 0.1]));
+        assert!("0.8,0.2".parse::<Ratios>().is_err());
+        assert!("1,-1,1".parse::<Ratios>().is_err());
+    }
+}
@@ -3,14 +3,25 @@
 
 
 
-use aya_ebpf::{ cty::c_long, macros::{btf_tracepoint, map}, maps::PerCpuArray, programs::BtfTracePointContext, EbpfContext};
-use ebsentinel_common::MAX_SYSCALLS;
+use aya_ebpf::{ cty::c_long, macros::{btf_tracepoint, map}, maps::{HashMap, PerCpuArray}, programs::BtfTracePointContext, EbpfContext};
+use ebsentinel_common::{MAX_SYSCALLS, MAX_TARGETS};
 
+//One block of MAX_SYSCALLS counters for every monitored process.
 #[map(name="SYSCALLS_COUNTERS")]
-static mut SYSCALLS_COUNTER: PerCpuArray<c_long> = PerCpuArray::with_max_entries(MAX_SYSCALLS, 0);
+static mut SYSCALLS_COUNTER: PerCpuArray<c_long> = PerCpuArray::with_max_entries(MAX_TARGETS * MAX_SYSCALLS, 0);
 
-#[map(name="MONITORED_PID")]
-static mut MONITORED_PID: PerCpuArray<u32> = PerCpuArray::with_max_entries(1, 0);
+//Maps a monitored pid to its block of counters.
+#[map(name="MONITORED_PIDS")]
+static mut MONITORED_PIDS: HashMap<u32, u32> = HashMap::with_max_entries(MAX_TARGETS, 0);
+
+//Processes monitoring every monitored pid, keyed by their pid and the monitored one, valued with their
+//start time so the monitors of a dead process can be reclaimed. Only used by user space.
+#[map(name="MONITORS")]
+static mut MONITORS: HashMap<u64, u64> = HashMap::with_max_entries(MAX_TARGETS * 16, 0);
+
+//Maps a block of counters to the pid it is handed to, claimed with BPF_NOEXIST. Only used by user space.
+#[map(name="SLOT_OWNERS")]
+static mut SLOT_OWNERS: HashMap<u32, u32> = HashMap::with_max_entries(MAX_TARGETS, 0);
 
 #[btf_tracepoint(function = "sys_enter")]
 pub fn ebsentinel(ctx: BtfTracePointContext) -> i32 {
@@ -23,17 +34,18 @@ pub fn ebsentinel(ctx: BtfTracePointContext) -> i32 {
 fn try_sys_enter(ctx: BtfTracePointContext) -> Result<i32, i32> {    
         let pid= ctx.tgid();
         let syscall_id: c_long = unsafe { ctx.arg(1) }; 
-        if let Some(monitored_pid) = unsafe { MONITORED_PID.get(0) }{
-            if pid == *monitored_pid {
-                if let Some(syscall_count) = unsafe {SYSCALLS_COUNTER.get_ptr_mut(syscall_id as u32)}{
-                    unsafe {
-                        *syscall_count+=1; 
-                    }
-    
-                };
-            }
+        if syscall_id < 0 || syscall_id >= MAX_SYSCALLS as c_long {
             return Ok(0);
         }
+        if let Some(slot) = unsafe { MONITORED_PIDS.get(&pid) }{
+            let index = *slot * MAX_SYSCALLS + syscall_id as u32;
+            if let Some(syscall_count) = unsafe {SYSCALLS_COUNTER.get_ptr_mut(index)}{
+                unsafe {
+                    *syscall_count+=1; 
+                }
+
+            };
+        }
     Ok(0)
 }
 
@@ -0,0 +1,12 @@
+pub mod pipeline;
+pub mod process_data;
+pub mod selection;
+
+use ebsentinel_common::syscalls::SYSCALL_NAMES;
+use sha2::{Digest, Sha256};
+
+/// SHA-256 of the syscall names of this architecture, two hosts agree on the syscall each
+/// feature counts if it matches.
+pub fn syscall_table_hash() -> String {
+    format!("{:x}", Sha256::digest(SYSCALL_NAMES.join("\n")))
+}
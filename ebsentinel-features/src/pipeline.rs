@@ -0,0 +1,189 @@
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::process_data::{DataProcessor, Normalizer};
+
+/// How a rate is computed from two counter snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rate {
+    /// Calls divided by the polling interval, like the recorder does online.
+    Nominal,
+    /// Calls divided by the time elapsed between the timestamps of the snapshots.
+    Elapsed,
+}
+
+/// How the rates of a sample are scaled into features.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scaler {
+    None,
+    /// Divided by the largest rate of the sample.
+    MaxNorm,
+    /// `ln(1 + rate)`, then divided by the largest value of the sample.
+    Log1pMaxNorm,
+}
+
+/// Definition of the features derived from raw counters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preprocessing {
+    pub rate: Rate,
+    /// Number of snapshots each rate spans.
+    pub window: usize,
+    pub scaler: Scaler,
+}
+
+impl Default for Preprocessing {
+    //What ebsentinel-rec stores as features.
+    fn default() -> Self {
+        Self {
+            rate: Rate::Nominal,
+            window: 1,
+            scaler: Scaler::MaxNorm,
+        }
+    }
+}
+
+/// Short description stored in the database metadata, e.g. `rate,max-norm`.
+impl fmt::Display for Preprocessing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.rate {
+            Rate::Nominal => f.write_str("rate")?,
+            Rate::Elapsed => f.write_str("elapsed-rate")?,
+        }
+        if self.window > 1 {
+            write!(f, "({})", self.window)?;
+        }
+        match self.scaler {
+            Scaler::None => Ok(()),
+            Scaler::MaxNorm => f.write_str(",max-norm"),
+            Scaler::Log1pMaxNorm => f.write_str(",log1p,max-norm"),
+        }
+    }
+}
+
+struct Snapshot {
+    timestamp_ns: i64,
+    counts: Vec<u64>,
+}
+
+/// A [`Preprocessing`] applied to the successive counter snapshots of one process.
+///
+/// The same pipeline derives the features of recorded snapshots and of live ones, so a model sees
+/// the same features in training and in detection.
+pub struct Pipeline {
+    preprocessing: Preprocessing,
+    polling_rate: Duration,
+    //The last `window` snapshots, oldest first.
+    history: VecDeque<Snapshot>,
+}
+
+impl Pipeline {
+    pub fn new(preprocessing: Preprocessing, polling_rate: Duration) -> Self {
+        Self {
+            preprocessing,
+            polling_rate,
+            history: VecDeque::new(),
+        }
+    }
+
+    /// Add the next snapshot and return its features, unless fewer than `window` snapshots came before it.
+    pub fn push(&mut self, timestamp_ns: i64, counts: &[u64]) -> Option<Vec<f32>> {
+        let window = self.preprocessing.window.max(1);
+        let features = match self.history.front() {
+            Some(oldest) if self.history.len() == window => {
+                let secs = match self.preprocessing.rate {
+                    Rate::Nominal => self.polling_rate.as_secs_f32() * window as f32,
+                    Rate::Elapsed => (timestamp_ns - oldest.timestamp_ns) as f32 / 1e9,
+                };
+                let frequency = if secs > 0.0 { 1.0 / secs } else { 0.0 };
+                let rates: Vec<f32> = counts
+                    .iter()
+                    .zip(&oldest.counts)
+                    //Subtracted as integers, f32 cannot tell apart counters above 2^24.
+                    .map(|(value, prev)| value.saturating_sub(*prev) as f32 * frequency)
+                    .collect();
+                Some(self.scale(rates))
+            }
+            _ => None,
+        };
+
+        if self.history.len() == window {
+            self.history.pop_front();
+        }
+        self.history.push_back(Snapshot {
+            timestamp_ns,
+            counts: counts.to_vec(),
+        });
+        features
+    }
+
+    fn scale(&self, rates: Vec<f32>) -> Vec<f32> {
+        match self.preprocessing.scaler {
+            Scaler::None => rates,
+            Scaler::MaxNorm => Normalizer.process(&rates).unwrap(),
+            Scaler::Log1pMaxNorm => {
+                let logs: Vec<f32> = rates.iter().map(|rate| rate.max(0.0).ln_1p()).collect();
+                Normalizer.process(&logs).unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{Pipeline, Preprocessing, Rate, Scaler};
+    use crate::process_data::{DataProcessor, Differentiator, Normalizer};
+
+    #[test]
+    fn default_matches_recorded_features() {
+        let polling_rate = Duration::from_millis(100);
+        let snapshots: Vec<Vec<u64>> = vec![vec![10, 0, 3], vec![25, 4, 3], vec![25, 9, 30], vec![1000, 9, 31]];
+
+        let mut differentiator = Differentiator::new(&polling_rate);
+        differentiator.prime(&snapshots[0]);
+        let mut pipeline = Pipeline::new(Preprocessing::default(), polling_rate);
+        assert_eq!(pipeline.push(0, &snapshots[0]), None);
+
+        for (i, counts) in snapshots.iter().enumerate().skip(1) {
+            let rates = differentiator.process(counts).unwrap();
+            let recorded = Normalizer.process(&rates).unwrap();
+            assert_eq!(pipeline.push(i as i64, counts), Some(recorded));
+        }
+        assert_eq!(Preprocessing::default().to_string(), "rate,max-norm");
+    }
+
+    #[test]
+    fn rates_of_large_counters() {
+        let polling_rate = Duration::from_millis(100);
+        let preprocessing = Preprocessing { scaler: Scaler::None, ..Preprocessing::default() };
+        let (first, second) = (vec![1 << 30, 1 << 25], vec![(1 << 30) + 3, (1 << 25) + 1]);
+
+        let mut differentiator = Differentiator::new(&polling_rate);
+        differentiator.prime(&first);
+        let mut pipeline = Pipeline::new(preprocessing, polling_rate);
+        assert_eq!(pipeline.push(0, &first), None);
+        let rates = pipeline.push(1, &second).unwrap();
+        assert_eq!(rates, [30.0, 10.0]);
+        assert_eq!(differentiator.process(&second).unwrap(), rates);
+    }
+
+    #[test]
+    fn elapsed_rate_over_window() {
+        let preprocessing = Preprocessing {
+            rate: Rate::Elapsed,
+            window: 2,
+            scaler: Scaler::None,
+        };
+        let mut pipeline = Pipeline::new(preprocessing.clone(), Duration::from_millis(100));
+        assert_eq!(pipeline.push(0, &[0, 0]), None);
+        assert_eq!(pipeline.push(500_000_000, &[5, 1]), None);
+        //Two snapshots back, 2 seconds earlier.
+        assert_eq!(pipeline.push(2_000_000_000, &[20, 1]), Some(vec![10.0, 0.5]));
+        assert_eq!(pipeline.push(2_500_000_000, &[20, 3]), Some(vec![7.5, 1.0]));
+        assert_eq!(preprocessing.to_string(), "elapsed-rate(2)");
+    }
+}
@@ -20,11 +20,16 @@ impl Differentiator{
         }
     
     }
+
+    /// Use `data` as the previous sample, e.g. counters that did not start at zero.
+    pub fn prime(&mut self, data: &[u64]) {
+        self.prev = data.to_vec();
+    }
 }
 
 impl DataProcessor<&[u64],Vec<f32>> for Differentiator {
     fn process(&mut self,data: &[u64] ) -> anyhow::Result<Vec<f32>> {
-        let rates : Vec<f32>= data.iter().enumerate().map(|(idx,value)| (*value as f32 - self.prev[idx] as f32) * self.frequency ).collect();
+        let rates : Vec<f32>= data.iter().enumerate().map(|(idx,value)| value.saturating_sub(self.prev[idx]) as f32 * self.frequency ).collect();
         self.prev=data.to_vec();
         Ok(rates)
     }
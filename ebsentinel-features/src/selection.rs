@@ -0,0 +1,129 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+/// Which features the model takes, chosen on the train samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "selection", rename_all = "kebab-case")]
+pub enum FeatureSelection {
+    All,
+    /// The syscalls called in at least one sample.
+    Seen,
+    /// The `k` syscalls whose feature varies the most, among the seen ones.
+    TopVariance { k: usize },
+}
+
+impl FromStr for FeatureSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let selection = match s.split_once(':') {
+            None if s == "all" => Self::All,
+            None if s == "seen" => Self::Seen,
+            Some(("top-variance", k)) => match k.parse()? {
+                0 => return Err(anyhow!("top-variance keeps 1 feature or more")),
+                k => Self::TopVariance { k },
+            },
+            _ => return Err(anyhow!("unknown feature selection {s}, expected all, seen or top-variance:K")),
+        };
+        Ok(selection)
+    }
+}
+
+impl fmt::Display for FeatureSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::All => f.write_str("all"),
+            Self::Seen => f.write_str("seen"),
+            Self::TopVariance { k } => write!(f, "top-variance:{k}"),
+        }
+    }
+}
+
+impl FeatureSelection {
+    /// The features kept of `samples`, None if they are all kept.
+    pub fn fit(&self, samples: impl IntoIterator<Item = Vec<f32>>) -> anyhow::Result<Option<Selection>> {
+        if *self == Self::All {
+            return Ok(None);
+        }
+        let (mut count, mut sums, mut squares) = (0usize, Vec::new(), Vec::new());
+        for sample in samples {
+            if count == 0 {
+                (sums, squares) = (vec![0.0f64; sample.len()], vec![0.0f64; sample.len()]);
+            } else if sample.len() != sums.len() {
+                bail!("samples of {} and {} features", sums.len(), sample.len());
+            }
+            for (i, value) in sample.iter().enumerate() {
+                sums[i] += *value as f64;
+                squares[i] += (*value as f64).powi(2);
+            }
+            count += 1;
+        }
+        //Features are rates, a syscall was called if its sum is not zero.
+        let mut seen: Vec<usize> = (0..sums.len()).filter(|i| sums[*i] != 0.0).collect();
+        if seen.is_empty() {
+            bail!("no syscall is called in the {count} samples the features are selected on");
+        }
+        if let Self::TopVariance { k } = *self {
+            let variance = |i: usize| squares[i] / count as f64 - (sums[i] / count as f64).powi(2);
+            seen.sort_by(|a, b| variance(*b).total_cmp(&variance(*a)).then(a.cmp(b)));
+            seen.truncate(k);
+            seen.sort_unstable();
+        }
+        Ok(Some(Selection { input_size: sums.len(), kept: seen }))
+    }
+}
+
+/// The features a model takes out of the full feature vectors.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    /// Size of the feature vectors before the selection.
+    pub input_size: usize,
+    /// Indices of the kept features, in increasing order.
+    pub kept: Vec<usize>,
+}
+
+impl Selection {
+    pub fn apply(&self, features: &[f32]) -> Vec<f32> {
+        self.kept.iter().map(|i| features[*i]).collect()
+    }
+
+    /// Indices of the dropped features that are not zero, the syscalls the model has never seen called.
+    pub fn unseen(&self, features: &[f32]) -> Vec<usize> {
+        features
+            .iter()
+            .enumerate()
+            .filter(|(i, value)| **value != 0.0 && self.kept.binary_search(i).is_err())
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{FeatureSelection, Selection};
+
+    #[test]
+    fn parses_selections() {
+        for s in ["all", "seen", "top-variance:40"] {
+            assert_eq!(s.parse::<FeatureSelection>().unwrap().to_string(), s);
+        }
+        assert!("top-variance:0".parse::<FeatureSelection>().is_err());
+        assert!("top-variance".parse::<FeatureSelection>().is_err());
+    }
+
+    #[test]
+    fn keeps_seen_and_varying_features() {
+        let samples = || vec![vec![0.0, 1.0, 0.5, 0.0], vec![0.0, 1.0, 0.0, 0.2], vec![0.0, 1.0, 1.0, 0.0]];
+        assert_eq!(FeatureSelection::All.fit(samples()).unwrap(), None);
+        let seen = FeatureSelection::Seen.fit(samples()).unwrap().unwrap();
+        assert_eq!(seen, Selection { input_size: 4, kept: vec![1, 2, 3] });
+        let top = FeatureSelection::TopVariance { k: 2 }.fit(samples()).unwrap().unwrap();
+        assert_eq!(top.kept, [2, 3]);
+
+        assert_eq!(top.apply(&[0.1, 0.2, 0.3, 0.4]), [0.3, 0.4]);
+        assert_eq!(top.unseen(&[0.1, 0.2, 0.3, 0.0]), [0, 1]);
+        assert!(FeatureSelection::Seen.fit(vec![vec![0.0; 4]]).is_err());
+    }
+}
@@ -1,11 +1,46 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
 
 #[derive(Parser)]
 pub struct Cli{
-    #[arg(value_name = "PID")]
-    pub pid: u32,
-    #[arg(value_name = "FILE", default_value="ebsentinel.db")]
-    pub db_file: String,
-    #[arg(short)]
-    pub test: bool
+    #[arg(value_name = "PID", required_unless_present = "command", conflicts_with = "command")]
+    pub pid: Option<u32>,
+    /// Database to record into [default: ebsentinel.db]
+    #[arg(value_name = "FILE")]
+    pub db_file: Option<String>,
+    /// Same as FILE, for use with a COMMAND
+    #[arg(long = "db", value_name = "FILE", conflicts_with = "db_file")]
+    pub db: Option<String>,
+    /// Share the sensor pinned under DIR, loading and pinning it first if needed
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = ebsentinel_core::sensor::DEFAULT_PIN_PATH)]
+    pub pin: Option<PathBuf>,
+    /// Name of the recording session, defaults to the binary name, pid and start time
+    #[arg(long, value_name = "NAME")]
+    pub session: Option<String>,
+    /// Label of the recorded samples, e.g. benign or attack
+    #[arg(long, value_name = "LABEL")]
+    pub label: Option<String>,
+    /// Free-form tag of the session, can be repeated
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+    /// What to store of every sample: the features, the raw counters they can be derived again from, or both
+    #[arg(long, value_enum, default_value_t = Store::Both)]
+    pub store: Store,
+    /// Launch COMMAND, record it from its first syscall until it exits, then exit with its status
+    #[arg(last = true, value_name = "COMMAND")]
+    pub command: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Store{
+    Features,
+    Raw,
+    Both,
+}
+
+impl Cli {
+    pub fn db_file(&self) -> &str {
+        self.db.as_deref().or(self.db_file.as_deref()).unwrap_or("ebsentinel.db")
+    }
 }
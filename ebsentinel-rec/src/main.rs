@@ -1,38 +1,114 @@
+use std::time::Duration;
+
 use clap::Parser;
-use cli::Cli;
-use ebsentinel_core::run_ebsentinel_ebpf;
-use ebsentinel_db::{EbsentinelDb, Syscalls};
-use tokio::signal;
-mod ebsentinel_db;
+use cli::{Cli, Store};
+use ebsentinel_core::{launch::{exit_code, Launched}, run_ebsentinel_ebpf, sample_source::{now_ns, Sample}};
+use ebsentinel_db::{Counts, EbsentinelDb, Metadata, Row, Syscalls, SPLITS};
+use tokio::{signal, time};
 mod cli;
+mod session;
 
+//Longest time a recorded sample waits in memory before being written.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
 
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    println!("{:?}",cli.db_file);
-    println!("{:?}",cli.test);
+    println!("{:?}",cli.db_file());
+
+    let mut db= EbsentinelDb::new(cli.db_file())?;
+    db.check_compatible(&Metadata::current())?;
 
-    let db= EbsentinelDb::new(cli.db_file);
+    //A launched command is registered while it is stopped, so not a single syscall is missed.
+    let launched = match cli.pid {
+        Some(_) => None,
+        None => Some(Launched::spawn_stopped(&cli.command)?),
+    };
+    let pid = cli.pid.or(launched.as_ref().map(Launched::pid)).unwrap();
+
+    let mut proc_mon =run_ebsentinel_ebpf(pid, cli.pin.as_deref())?;
+    let session = session::collect(cli.session, cli.label, cli.tags, pid, proc_mon.polling_rate());
+    println!("session: {}", session.name);
+    let session_id = db.start_session(&session)?;
+    let store = cli.store;
+    let row = |sample: Sample| Row {
+        session_id,
+        pid,
+        timestamp_ns: sample.timestamp_ns,
+        label: session.label.clone(),
+        syscalls: (store != Store::Raw).then(|| Syscalls::new(sample.features)),
+        counts: (store != Store::Features).then(|| Counts::new(sample.counts)),
+        split: None,
+    };
 
-    let mut proc_mon =run_ebsentinel_ebpf(cli.pid)?;
     let mut rx=proc_mon.run()?;
-    tokio::spawn(async move {
-        loop {
-            let rates=rx.recv().await;
-            let data= Syscalls::new(rates.unwrap());
-            match cli.test {
-                true => db.add_test_data(&data),
-                false => db.add_train_data(&data),
-            }
-        }
-    });
-    
+    let mut child = launched.map(Launched::resume).transpose()?;
+    let mut status = None;
+    let mut flush = time::interval(FLUSH_INTERVAL);
     let ctrl_c = signal::ctrl_c();
+    tokio::pin!(ctrl_c);
     println!("Waiting for Ctrl-C...");
-    ctrl_c.await?;
+    loop {
+        tokio::select! {
+            sample = rx.recv() => {
+                let Some(sample) = sample else { break };
+                record(&mut db, row(sample));
+            }
+            _ = flush.tick() => {
+                if let Err(e) = db.flush() {
+                    eprintln!("write failed, {} samples kept for the next flush: {e:#}", db.pending());
+                }
+            }
+            exit = async { child.as_mut().unwrap().await }, if child.is_some() => {
+                status = Some(exit??);
+                break;
+            }
+            result = &mut ctrl_c => {
+                result?;
+                break;
+            }
+        }
+    }
     println!("Exiting...");
+    //The syscalls since the last poll, e.g. the exit of the launched command, and the samples
+    //already read from the sensor are still recorded.
+    proc_mon.finish();
+    while let Some(sample) = rx.recv().await {
+        record(&mut db, row(sample));
+    }
+    //Failures are reported like in record(), the status of the launched command is still the exit code.
+    if let Err(e) = db.flush() {
+        eprintln!("write failed: {e:#}");
+    }
+    if let Err(e) = db.end_session(session_id, now_ns()) {
+        eprintln!("cannot end the session: {e:#}");
+    }
 
+    //The new samples are split like the previous ones, which stay where they are; ebsentinel-dataset split changes how.
+    match db.split_config().and_then(|config| db.split_new(&config.unwrap_or_default())) {
+        Ok(sizes) => {
+            for (table, size) in SPLITS.iter().zip(sizes) {
+                println!("{table}: {size}");
+            }
+        }
+        Err(e) => eprintln!("cannot split the new samples: {e:#}"),
+    }
+
+    //Ctrl-C also reached the launched command, its status is the one of its exit.
+    if let (None, Some(child)) = (status, child) {
+        status = Some(child.await??);
+    }
+    if let Some(status) = status {
+        drop((proc_mon, db));
+        std::process::exit(exit_code(status));
+    }
     Ok(())
 }
+
+//Failed writes are reported and recording goes on, the samples are retried at the next flush.
+fn record(db: &mut EbsentinelDb, row: Row) {
+    if let Err(e) = db.add_sample(row) {
+        eprintln!("write failed: {e:#}");
+    }
+}
@@ -0,0 +1,39 @@
+use std::{fs, time::Duration};
+
+use ebsentinel_core::sample_source::now_ns;
+use ebsentinel_db::Session;
+use sha2::{Digest, Sha256};
+
+/// Describe a new recording session of `pid` from what the host tells about it.
+pub fn collect(name: Option<String>, label: Option<String>, tags: Vec<String>, pid: u32, polling_rate: Duration) -> Session {
+    let binary = fs::read_link(format!("/proc/{pid}/exe")).ok();
+    let binary_sha256 = binary.as_ref().and_then(|path| fs::read(path).ok()).map(|bytes| format!("{:x}", Sha256::digest(bytes)));
+    let started_at = now_ns();
+
+    let name = name.unwrap_or_else(|| {
+        let program = binary
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "pid".to_string());
+        format!("{program}-{pid}-{}", started_at / 1_000_000_000)
+    });
+
+    Session {
+        name,
+        label,
+        tags,
+        host: read_trimmed("/proc/sys/kernel/hostname"),
+        pid,
+        binary: binary.map(|path| path.to_string_lossy().into_owned()),
+        binary_sha256,
+        kernel: read_trimmed("/proc/sys/kernel/osrelease"),
+        polling_rate_ms: polling_rate.as_millis() as u64,
+        recorder_version: env!("CARGO_PKG_VERSION").to_string(),
+        started_at,
+    }
+}
+
+fn read_trimmed(path: &str) -> String {
+    fs::read_to_string(path).map(|s| s.trim().to_string()).unwrap_or_default()
+}
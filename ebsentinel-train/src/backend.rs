@@ -0,0 +1,2 @@
+//The burn backend the model runs on, chosen by the cargo features of this crate.
+autoencoder::select_backend!();
@@ -0,0 +1,236 @@
+use std::path::PathBuf;
+
+use autoencoder::calibration::Strategy;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use ebsentinel_db::SPLITS;
+use ebsentinel_features::selection::FeatureSelection;
+
+use crate::schedule::LrSchedule;
+
+/// Trains the model, unless a command is given.
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli{
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub train: TrainArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Score labeled samples with a trained model: precision, recall, false positives per hour,
+    /// ROC-AUC, PR-AUC and a threshold sweep
+    Evaluate(EvaluateArgs),
+    /// Train one model per point of a search space and keep the best one as a run
+    Search(SearchArgs),
+    /// List, compare, promote, sign and prune the trained runs
+    Runs(RunsArgs),
+    /// Generate the ed25519 keypair model bundles are signed with
+    Keygen(KeygenArgs),
+}
+
+/// Options left out keep the value of --config, or the default.
+#[derive(Args)]
+pub struct TrainArgs{
+    /// Database recorded by ebsentinel-rec
+    #[arg(long = "db", value_name = "FILE", default_value = "ebsentinel.db")]
+    pub db_file: String,
+    /// Directory of the runs, every training writes its model, config and logs to a new runs/<NAME>
+    #[arg(long, value_name = "DIR", default_value = "experiment")]
+    pub artifact_dir: String,
+    /// Tag of the run, to select it by, can be repeated
+    #[arg(long = "tag", value_name = "TAG")]
+    pub tags: Vec<String>,
+    /// Full TrainingConfig JSON file, e.g. the config.json of a previous run
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Continue training the model of this run (a name, a tag, latest or promoted) on the samples
+    /// of --db, starting from its config
+    #[arg(long, value_name = "RUN", conflicts_with = "config")]
+    pub fine_tune: Option<String>,
+    /// Mix this many train samples of the data of the fine-tuned run into every new one, e.g. 0.25,
+    /// so that the model does not forget it
+    #[arg(long, value_name = "RATIO", requires = "fine_tune")]
+    pub replay: Option<f64>,
+    /// Database the replayed samples come from [default: the one the fine-tuned run was trained on]
+    #[arg(long, value_name = "FILE", requires = "replay")]
+    pub replay_db: Option<String>,
+    /// Size of the latent space of the autoencoder [default: 64]
+    #[arg(long, value_name = "SIZE")]
+    pub latent_size: Option<usize>,
+    /// Width of the hidden layers of the autoencoder [default: half the input]
+    #[arg(long, value_name = "SIZE")]
+    pub hidden_size: Option<usize>,
+    /// [default: 200]
+    #[arg(long)]
+    pub epochs: Option<usize>,
+    /// [default: 16]
+    #[arg(long)]
+    pub batch_size: Option<usize>,
+    /// [default: 0.000141]
+    #[arg(long, value_name = "RATE")]
+    pub learning_rate: Option<f64>,
+    /// How the learning rate decays: constant, cosine[:MIN_LR] or step:EPOCHS:GAMMA [default: constant]
+    #[arg(long, value_name = "SCHEDULE")]
+    pub lr_schedule: Option<LrSchedule>,
+    /// Epochs over which the learning rate first rises linearly [default: 0]
+    #[arg(long, value_name = "EPOCHS")]
+    pub warmup_epochs: Option<usize>,
+    /// Stop once the valid loss has not improved for this many epochs
+    #[arg(long, value_name = "EPOCHS")]
+    pub patience: Option<usize>,
+    /// Keep the model of the epoch with the lowest valid loss instead of the last one
+    #[arg(long)]
+    pub keep_best: bool,
+    /// Seed of the weight initialization and of the shuffles [default: 42]
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Data loading workers [default: 2]
+    #[arg(long, value_name = "COUNT")]
+    pub workers: Option<usize>,
+    /// Derive the features again from the raw counters, with the preprocessing described in this
+    /// JSON file, e.g. {"rate": "elapsed", "window": 4, "scaler": "log1p-max-norm"}
+    #[arg(long, value_name = "FILE")]
+    pub preprocessing: Option<PathBuf>,
+    /// Features the model takes: all, seen (the syscalls called in the train samples) or
+    /// top-variance:K (the K seen syscalls that vary the most) [default: all]
+    #[arg(long, value_name = "SELECTION")]
+    pub feature_selection: Option<FeatureSelection>,
+    /// How the threshold is derived from the losses of the calibration split:
+    /// max, percentile:P, mean-std:K or pot:RISK
+    #[arg(long, value_name = "STRATEGY", default_value = "max")]
+    pub calibration: Strategy,
+    /// Held-out split the threshold is calibrated on
+    #[arg(long, value_name = "SPLIT", default_value = "valid", value_parser = SPLITS)]
+    pub calibration_split: String,
+    /// Sign the model bundle with this secret key, see keygen
+    #[arg(long, value_name = "FILE")]
+    pub signing_key: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct EvaluateArgs{
+    /// Database with labeled samples, e.g. recorded with ebsentinel-rec --label attack
+    #[arg(long = "db", value_name = "FILE", default_value = "ebsentinel.db")]
+    pub db_file: String,
+    /// Directory of the runs
+    #[arg(long, value_name = "DIR", default_value = "experiment")]
+    pub artifact_dir: String,
+    /// Run to evaluate: a name, a tag, latest or promoted [default: promoted, else latest]
+    #[arg(long, value_name = "RUN")]
+    pub model: Option<String>,
+    /// Only evaluate the samples of this split [default: every sample]
+    #[arg(long, value_name = "SPLIT", value_parser = SPLITS)]
+    pub split: Option<String>,
+    /// Label of the normal samples, can be repeated, any other label is an attack
+    #[arg(long = "benign-label", value_name = "LABEL", default_value = "benign")]
+    pub benign_labels: Vec<String>,
+    /// Threshold the alerts are raised above [default: the calibrated one]
+    #[arg(long, value_name = "THRESH")]
+    pub threshold: Option<f32>,
+    /// Number of thresholds of the sweep
+    #[arg(long, value_name = "STEPS", default_value_t = 20)]
+    pub sweep: usize,
+    /// Directory evaluation.json and sweep.csv are written to [default: the directory of the run]
+    #[arg(long, value_name = "DIR")]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct SearchArgs{
+    /// JSON file giving the values of each searched field, e.g.
+    /// {"latent_size": [32, 64], "learning_rate": {"low": 1e-5, "high": 1e-3, "log": true}}
+    #[arg(long, value_name = "FILE")]
+    pub space: PathBuf,
+    /// grid tries every combination of the listed values, random draws --trials of them
+    #[arg(long, value_enum, default_value_t = SearchStrategy::Grid)]
+    pub strategy: SearchStrategy,
+    /// Number of random trials
+    #[arg(long, value_name = "COUNT", default_value_t = 10)]
+    pub trials: usize,
+    /// Seed of the random draws
+    #[arg(long, value_name = "SEED", default_value_t = 0)]
+    pub search_seed: u64,
+    /// What the trials are ranked by, the metrics need --eval-db
+    #[arg(long, value_enum, default_value_t = Rank::ValidLoss)]
+    pub rank: Rank,
+    /// Database with labeled samples the trials are evaluated on
+    #[arg(long, value_name = "FILE")]
+    pub eval_db: Option<String>,
+    /// Only evaluate the samples of this split of --eval-db [default: every sample]
+    #[arg(long, value_name = "SPLIT", value_parser = SPLITS)]
+    pub eval_split: Option<String>,
+    /// Label of the normal samples of --eval-db, can be repeated
+    #[arg(long = "benign-label", value_name = "LABEL", default_value = "benign")]
+    pub benign_labels: Vec<String>,
+    /// Base config of the trials, the searched fields override it
+    #[command(flatten)]
+    pub train: TrainArgs,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SearchStrategy {
+    Grid,
+    Random,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum Rank {
+    /// Lowest mean reconstruction loss on the valid split
+    ValidLoss,
+    RocAuc,
+    PrAuc,
+    /// F1 at the calibrated threshold
+    F1,
+}
+
+#[derive(Args)]
+pub struct RunsArgs{
+    /// Directory of the runs
+    #[arg(long, value_name = "DIR", default_value = "experiment", global = true)]
+    pub artifact_dir: String,
+    #[command(subcommand)]
+    pub command: RunsCommand,
+}
+
+#[derive(Subcommand)]
+pub enum RunsCommand {
+    /// List the runs with their tags, data and metrics
+    List,
+    /// Show the metrics, data and config fields that differ between runs
+    Compare {
+        /// Runs to compare: names, tags, latest or promoted
+        #[arg(value_name = "RUN", num_args = 2.., required = true)]
+        runs: Vec<String>,
+    },
+    /// Make a run the default model of ebsentinel and ebsentinel-train evaluate
+    Promote {
+        #[arg(value_name = "RUN")]
+        run: String,
+    },
+    /// Sign the model bundle of a run
+    Sign {
+        #[arg(value_name = "RUN")]
+        run: String,
+        /// Secret key, see keygen
+        #[arg(long, value_name = "FILE")]
+        key: PathBuf,
+    },
+    /// Delete all but the latest runs, the promoted one is always kept
+    Prune {
+        /// Number of latest runs to keep
+        #[arg(long, value_name = "COUNT", default_value_t = 5)]
+        keep: usize,
+        /// Also keep every tagged run
+        #[arg(long)]
+        keep_tagged: bool,
+    },
+}
+
+#[derive(Args)]
+pub struct KeygenArgs{
+    /// Secret key file, the public key is written next to it with a .pub extension
+    #[arg(long, value_name = "FILE", default_value = "ebsentinel.key")]
+    pub output: PathBuf,
+}
@@ -1,5 +1,8 @@
 use autoencoder::data::Syscalls;
-use burn::data::dataset::{transform::{Mapper, MapperDataset}, Dataset, SqliteDataset};
+use burn::data::dataset::{transform::{Mapper, MapperDataset}, Dataset, InMemDataset, SqliteDataset};
+use ebsentinel_db::EbsentinelDb;
+use ebsentinel_features::{pipeline::Preprocessing, selection::Selection};
+use rand::{rngs::StdRng, seq::index, Rng, SeedableRng};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone,Serialize,Deserialize)]
@@ -8,15 +11,16 @@ struct SyscallsRaw{
     syscalls: Vec<u8>
 }
 
-struct SyscallsRawToSyscalls;
+struct SyscallsRawToSyscalls{
+    //Layout of the database, sparse rows are densified here.
+    layout: String
+}
 
 impl Mapper<SyscallsRaw, Syscalls> for SyscallsRawToSyscalls {
     /// Convert a raw syscall to Syscall
     fn map(&self, item: &SyscallsRaw) -> Syscalls {
-        // Ensure the image dimensions are correct.
-
         Syscalls {
-            counts: bincode::deserialize(&item.syscalls).unwrap()
+            counts: ebsentinel_db::decode(&self.layout, &item.syscalls).unwrap()
         }
     }
 }
@@ -24,30 +28,84 @@ impl Mapper<SyscallsRaw, Syscalls> for SyscallsRawToSyscalls {
 type MappedDataset = MapperDataset<SqliteDataset<SyscallsRaw>,SyscallsRawToSyscalls,SyscallsRaw>;
 
 pub struct SyscallsDataset{
-    dataset: MappedDataset
+    dataset: Box<dyn Dataset<Syscalls>>
 }
 
 impl SyscallsDataset {
-    /// Creates a new train dataset.
-    pub fn train(db_file: &str) -> Self {
-        Self::new(db_file,"train")
+    /// Loads `split` with its recorded features, or with features derived again from its raw counters.
+    pub fn load(db_file: &str, split: &str, preprocessing: Option<&Preprocessing>) -> anyhow::Result<Self> {
+        let db = EbsentinelDb::new(db_file)?;
+        let Some(preprocessing) = preprocessing else {
+            return Ok(Self::new(db_file, split, db.metadata()?.feature_layout));
+        };
+        let features = db.derive_features(split, preprocessing)?;
+        let items = features.into_iter().map(|counts| Syscalls { counts }).collect();
+        Ok(Self { dataset: Box::new(InMemDataset::new(items)) })
+    }
+
+    /// This dataset followed by `count` samples of `old` drawn at random, without replacement
+    /// unless `old` has fewer samples.
+    pub fn with_replay(self, old: &SyscallsDataset, count: usize, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let indices = if count <= old.len() {
+            index::sample(&mut rng, old.len(), count).into_vec()
+        } else {
+            (0..count).map(|_| rng.gen_range(0..old.len())).collect()
+        };
+        let replayed = indices.into_iter().filter_map(|i| old.get(i)).collect();
+        Self { dataset: Box::new(Replayed { new: self.dataset, old: InMemDataset::new(replayed) }) }
     }
 
-    /// Creates a new test dataset.
-    pub fn test(db_file: &str) -> Self {
-        Self::new(db_file,"test")
+    /// This dataset with only the features `selection` keeps.
+    pub fn select(self, selection: Selection) -> Self {
+        Self { dataset: Box::new(Selected { dataset: self.dataset, selection }) }
     }
 
-    fn new(db_file: &str,split: &str) -> Self {
+    fn new(db_file: &str,split: &str, layout: String) -> Self {
         let dataset_raw: SqliteDataset<SyscallsRaw> = SqliteDataset::from_db_file(db_file, split).unwrap();
 
         // Create the MapperDataset for InMemDataset<MnistItemRaw> to transform
         // items (MnistItemRaw -> MnistItem)
-        let dataset = MapperDataset::new(dataset_raw, SyscallsRawToSyscalls);
+        let dataset: MappedDataset = MapperDataset::new(dataset_raw, SyscallsRawToSyscalls { layout });
+
+        Self { dataset: Box::new(dataset) }
+    }
+
+}
+
+//New samples, then the replayed ones.
+struct Replayed {
+    new: Box<dyn Dataset<Syscalls>>,
+    old: InMemDataset<Syscalls>,
+}
+
+impl Dataset<Syscalls> for Replayed {
+    fn get(&self, index: usize) -> Option<Syscalls> {
+        match index.checked_sub(self.new.len()) {
+            Some(index) => self.old.get(index),
+            None => self.new.get(index),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.new.len() + self.old.len()
+    }
+}
 
-        Self { dataset }
+struct Selected {
+    dataset: Box<dyn Dataset<Syscalls>>,
+    selection: Selection,
+}
+
+impl Dataset<Syscalls> for Selected {
+    fn get(&self, index: usize) -> Option<Syscalls> {
+        let item = self.dataset.get(index)?;
+        Some(Syscalls { counts: self.selection.apply(&item.counts) })
     }
 
+    fn len(&self) -> usize {
+        self.dataset.len()
+    }
 }
 
 impl Dataset<Syscalls> for SyscallsDataset{
@@ -64,16 +122,16 @@ impl Dataset<Syscalls> for SyscallsDataset{
 mod test{
     use std::num::NonZero;
 
-    use burn::{backend::Wgpu, data::{dataloader::batcher::Batcher, dataset::{transform::Window, Dataset}}};
+    use burn::{data::{dataloader::batcher::Batcher, dataset::{transform::Window, Dataset}}};
 
     use autoencoder::data::SyscallBatcher;
 
     use super::SyscallsDataset;
     #[test]
     pub fn it_works(){
-        type MyBackend = Wgpu<f32, i32>;
-        let device = burn::backend::wgpu::WgpuDevice::default();
-        let dataset= SyscallsDataset::train("ebsentinel.db");
+        type MyBackend = crate::backend::Backend;
+        let device = crate::backend::device();
+        let dataset= SyscallsDataset::load("ebsentinel.db", "train", None).unwrap();
         let items = dataset.window(0, NonZero::new(dataset.len()).unwrap()).unwrap();
         let batcher: SyscallBatcher<MyBackend> = SyscallBatcher::new(device);
         let batch = batcher.batch(items.clone());
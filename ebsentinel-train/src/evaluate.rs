@@ -0,0 +1,338 @@
+use std::{collections::{BTreeMap, HashMap}, fmt::Write as _, fs, path::Path, time::Duration};
+
+use anyhow::{bail, Context};
+use autoencoder::{calibration::Calibration, data::Syscalls, registry::Registry};
+use ebsentinel_db::EbsentinelDb;
+use ebsentinel_features::pipeline::Pipeline;
+use serde::Serialize;
+
+use crate::{backend, cli::EvaluateArgs, infer, load_model, load_selection, MyBackend};
+
+//Polling rate of the samples recorded before sessions existed.
+const DEFAULT_POLLING_RATE_MS: u64 = 100;
+
+/// The loss of a labeled sample.
+#[derive(Debug, Clone, Copy)]
+pub struct Scored {
+    pub score: f32,
+    pub attack: bool,
+    /// Time the sample covers, in hours.
+    pub hours: f64,
+}
+
+/// Alerts raised at one threshold, a sample is flagged when its loss is above it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Detection {
+    pub threshold: f32,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub true_negatives: usize,
+    pub false_negatives: usize,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    pub false_positive_rate: f64,
+    pub false_positives_per_hour: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Evaluation {
+    pub db: String,
+    /// Split evaluated, every sample if none.
+    pub split: Option<String>,
+    pub attacks: usize,
+    pub benign: usize,
+    /// Samples left out for lack of a label or of features.
+    pub unlabeled: usize,
+    pub missing_features: usize,
+    pub benign_hours: f64,
+    /// Only defined when there are both benign and attack samples.
+    pub roc_auc: Option<f64>,
+    pub pr_auc: Option<f64>,
+    pub at_threshold: Detection,
+    pub sweep: Vec<Detection>,
+}
+
+/// Score the labeled samples of a database with a trained model and write the reports.
+pub fn run(args: &EvaluateArgs) -> anyhow::Result<()> {
+    let mut registry = Registry::open(&args.artifact_dir)?;
+    let run = registry.select(args.model.as_deref())?.map(|run| run.name.clone());
+    let evaluation = evaluate(args)?;
+    let output = args.output.clone().unwrap_or(registry.model_dir(args.model.as_deref())?);
+    write(&evaluation, &output)?;
+    //The metrics of the last evaluation of a run are kept in the registry, to compare runs by.
+    if let Some(run) = run {
+        let detection = &evaluation.at_threshold;
+        let mut metrics = BTreeMap::from([
+            ("precision".to_string(), detection.precision),
+            ("recall".to_string(), detection.recall),
+            ("f1".to_string(), detection.f1),
+            ("false_positives_per_hour".to_string(), detection.false_positives_per_hour),
+        ]);
+        metrics.extend(evaluation.roc_auc.map(|auc| ("roc_auc".to_string(), auc)));
+        metrics.extend(evaluation.pr_auc.map(|auc| ("pr_auc".to_string(), auc)));
+        registry.set_metrics(&run, metrics)?;
+    }
+
+    let detection = &evaluation.at_threshold;
+    let auc = |auc: Option<f64>| auc.map_or("-".to_string(), |auc| format!("{auc:.4}"));
+    println!("{} attack and {} benign samples ({:.2} h)", evaluation.attacks, evaluation.benign, evaluation.benign_hours);
+    println!(
+        "threshold {}: precision {:.4}, recall {:.4}, F1 {:.4}, {:.2} false positives per hour",
+        detection.threshold, detection.precision, detection.recall, detection.f1, detection.false_positives_per_hour
+    );
+    println!("ROC-AUC {}, PR-AUC {}", auc(evaluation.roc_auc), auc(evaluation.pr_auc));
+    println!("reports written to {}", output.display());
+    Ok(())
+}
+
+/// Score the labeled samples of a database with the model of the selected run.
+pub fn evaluate(args: &EvaluateArgs) -> anyhow::Result<Evaluation> {
+    let device = backend::device();
+    let model_dir = Registry::open(&args.artifact_dir)?.model_dir(args.model.as_deref())?;
+    let model_dir = model_dir.to_string_lossy();
+    let (config, model) = load_model(&model_dir)?;
+    let selection = load_selection(&model_dir)?;
+    let threshold = match args.threshold {
+        Some(threshold) => threshold,
+        None => Calibration::load(format!("{model_dir}/calibration.json"))
+            .context("the model has no calibrated threshold, pass --threshold")?
+            .threshold,
+    };
+
+    let db = EbsentinelDb::new(&args.db_file)?;
+    let feature_size = db.metadata()?.feature_size;
+    let input_size = selection.as_ref().map_or(config.model.input_size, |selection| selection.input_size);
+    if feature_size != input_size {
+        bail!("the model takes {input_size} inputs but the samples have {feature_size} features");
+    }
+    let polling_rates: HashMap<i64, u64> = db
+        .sessions()?
+        .into_iter()
+        .map(|stored| (stored.session_id, stored.session.polling_rate_ms))
+        .collect();
+
+    let mut pipelines: HashMap<Option<i64>, Pipeline> = HashMap::new();
+    let mut scored = Vec::new();
+    let (mut unlabeled, mut missing_features) = (0, 0);
+    db.for_each_sample(|sample| {
+        let polling_rate_ms = sample
+            .session_id
+            .and_then(|session_id| polling_rates.get(&session_id).copied())
+            .unwrap_or(DEFAULT_POLLING_RATE_MS);
+        let features = match (&config.preprocessing, &sample.counts) {
+            //Every sample goes through the pipeline of its session, whatever its split.
+            (Some(preprocessing), Some(counts)) => pipelines
+                .entry(sample.session_id)
+                .or_insert_with(|| Pipeline::new(preprocessing.clone(), Duration::from_millis(polling_rate_ms)))
+                .push(sample.timestamp_ns.unwrap_or_default(), counts),
+            (Some(_), None) => None,
+            (None, _) => sample.features,
+        };
+        if args.split.as_deref().is_some_and(|split| sample.split != Some(split)) {
+            return Ok(());
+        }
+        let Some(label) = sample.label else {
+            unlabeled += 1;
+            return Ok(());
+        };
+        let Some(features) = features else {
+            missing_features += 1;
+            return Ok(());
+        };
+        let counts = match &selection {
+            Some(selection) => selection.apply(&features),
+            None => features,
+        };
+        let (_, score) = infer::<MyBackend>(device.clone(), &model, Syscalls { counts });
+        scored.push(Scored {
+            score,
+            attack: !args.benign_labels.contains(&label),
+            hours: polling_rate_ms as f64 / 3_600_000.0,
+        });
+        Ok(())
+    })?;
+    if scored.is_empty() {
+        bail!("no labeled sample to evaluate in {}", args.db_file);
+    }
+
+    Ok(Evaluation {
+        db: args.db_file.clone(),
+        split: args.split.clone(),
+        attacks: scored.iter().filter(|sample| sample.attack).count(),
+        benign: scored.iter().filter(|sample| !sample.attack).count(),
+        unlabeled,
+        missing_features,
+        benign_hours: scored.iter().filter(|sample| !sample.attack).map(|sample| sample.hours).sum(),
+        roc_auc: roc_auc(&scored),
+        pr_auc: pr_auc(&scored),
+        at_threshold: detect(&scored, threshold),
+        sweep: sweep(&scored, args.sweep).iter().map(|threshold| detect(&scored, *threshold)).collect(),
+    })
+}
+
+/// Write `evaluation.json` and the sweep as `sweep.csv` to `output`.
+pub fn write(evaluation: &Evaluation, output: &Path) -> anyhow::Result<()> {
+    fs::create_dir_all(output)?;
+    fs::write(output.join("evaluation.json"), serde_json::to_string_pretty(evaluation)?)?;
+    fs::write(output.join("sweep.csv"), sweep_csv(&evaluation.sweep))?;
+    Ok(())
+}
+
+/// The alerts raised at `threshold`.
+pub fn detect(scored: &[Scored], threshold: f32) -> Detection {
+    let (mut tp, mut fp, mut tn, mut fn_) = (0, 0, 0, 0);
+    for sample in scored {
+        match (sample.attack, sample.score > threshold) {
+            (true, true) => tp += 1,
+            (false, true) => fp += 1,
+            (false, false) => tn += 1,
+            (true, false) => fn_ += 1,
+        }
+    }
+    let ratio = |num: usize, den: usize| if den == 0 { 0.0 } else { num as f64 / den as f64 };
+    let precision = ratio(tp, tp + fp);
+    let recall = ratio(tp, tp + fn_);
+    let benign_hours: f64 = scored.iter().filter(|sample| !sample.attack).map(|sample| sample.hours).sum();
+    Detection {
+        threshold,
+        true_positives: tp,
+        false_positives: fp,
+        true_negatives: tn,
+        false_negatives: fn_,
+        precision,
+        recall,
+        f1: if precision + recall > 0.0 { 2.0 * precision * recall / (precision + recall) } else { 0.0 },
+        false_positive_rate: ratio(fp, fp + tn),
+        false_positives_per_hour: if benign_hours > 0.0 { fp as f64 / benign_hours } else { 0.0 },
+    }
+}
+
+//`steps` thresholds spread over the quantiles of the losses, from none to every sample flagged.
+fn sweep(scored: &[Scored], steps: usize) -> Vec<f32> {
+    let mut scores: Vec<f32> = scored.iter().map(|sample| sample.score).collect();
+    scores.sort_by(f32::total_cmp);
+    let mut thresholds: Vec<f32> = (0..steps)
+        .map(|step| {
+            let q = if steps > 1 { step as f64 / (steps - 1) as f64 } else { 1.0 };
+            scores[((q * (scores.len() - 1) as f64).round() as usize).min(scores.len() - 1)]
+        })
+        .collect();
+    //A loss equal to the threshold is not flagged, start just under the lowest one so every sample is.
+    if let Some(lowest) = thresholds.first_mut() {
+        *lowest = lowest.next_down();
+    }
+    thresholds.dedup();
+    thresholds
+}
+
+/// Area under the ROC curve, the probability that an attack sample has a higher loss than a benign one.
+pub fn roc_auc(scored: &[Scored]) -> Option<f64> {
+    let attacks = scored.iter().filter(|sample| sample.attack).count();
+    let benign = scored.len() - attacks;
+    if attacks == 0 || benign == 0 {
+        return None;
+    }
+    let mut sorted: Vec<&Scored> = scored.iter().collect();
+    sorted.sort_by(|a, b| a.score.total_cmp(&b.score));
+
+    //Mann-Whitney U, tied losses share their mean rank.
+    let mut attack_ranks = 0.0;
+    let mut start = 0;
+    while start < sorted.len() {
+        let end = start + sorted[start..].iter().take_while(|sample| sample.score == sorted[start].score).count();
+        let rank = (start + 1 + end) as f64 / 2.0;
+        attack_ranks += rank * sorted[start..end].iter().filter(|sample| sample.attack).count() as f64;
+        start = end;
+    }
+    let attacks = attacks as f64;
+    Some((attack_ranks - attacks * (attacks + 1.0) / 2.0) / (attacks * benign as f64))
+}
+
+/// Area under the precision-recall curve, as the average precision over the attack samples.
+pub fn pr_auc(scored: &[Scored]) -> Option<f64> {
+    let attacks = scored.iter().filter(|sample| sample.attack).count();
+    if attacks == 0 || attacks == scored.len() {
+        return None;
+    }
+    let mut sorted: Vec<&Scored> = scored.iter().collect();
+    sorted.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let (mut tp, mut fp, mut area, mut last_recall) = (0, 0, 0.0, 0.0);
+    let mut start = 0;
+    while start < sorted.len() {
+        let end = start + sorted[start..].iter().take_while(|sample| sample.score == sorted[start].score).count();
+        let flagged = sorted[start..end].iter().filter(|sample| sample.attack).count();
+        tp += flagged;
+        fp += end - start - flagged;
+        let recall = tp as f64 / attacks as f64;
+        area += (recall - last_recall) * tp as f64 / (tp + fp) as f64;
+        last_recall = recall;
+        start = end;
+    }
+    Some(area)
+}
+
+fn sweep_csv(sweep: &[Detection]) -> String {
+    let mut csv = String::from(
+        "threshold,true_positives,false_positives,true_negatives,false_negatives,precision,recall,f1,false_positive_rate,false_positives_per_hour\n",
+    );
+    for d in sweep {
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{},{}",
+            d.threshold,
+            d.true_positives,
+            d.false_positives,
+            d.true_negatives,
+            d.false_negatives,
+            d.precision,
+            d.recall,
+            d.f1,
+            d.false_positive_rate,
+            d.false_positives_per_hour
+        );
+    }
+    csv
+}
+
+#[cfg(test)]
+mod test {
+    use super::{detect, pr_auc, roc_auc, sweep, Scored};
+
+    fn scored(samples: &[(f32, bool)]) -> Vec<Scored> {
+        samples.iter().map(|(score, attack)| Scored { score: *score, attack: *attack, hours: 0.5 }).collect()
+    }
+
+    #[test]
+    fn detection_at_threshold() {
+        let samples = scored(&[(0.1, false), (0.2, false), (0.6, false), (0.5, true), (0.9, true)]);
+        let detection = detect(&samples, 0.4);
+        assert_eq!((detection.true_positives, detection.false_positives, detection.false_negatives), (2, 1, 0));
+        assert!((detection.precision - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(detection.recall, 1.0);
+        //One false positive over 3 benign samples of half an hour.
+        assert!((detection.false_positives_per_hour - 1.0 / 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn areas_under_curves() {
+        let separated = scored(&[(0.1, false), (0.2, false), (0.8, true), (0.9, true)]);
+        assert_eq!(roc_auc(&separated), Some(1.0));
+        assert_eq!(pr_auc(&separated), Some(1.0));
+
+        //Of the four attack/benign pairs, one is misordered and one is tied.
+        let mixed = scored(&[(0.1, false), (0.5, false), (0.5, true), (0.3, true)]);
+        assert_eq!(roc_auc(&mixed), Some(0.625));
+        assert!(roc_auc(&scored(&[(0.1, false)])).is_none());
+    }
+
+    #[test]
+    fn sweep_covers_every_outcome() {
+        let samples = scored(&[(0.1, false), (0.2, false), (0.8, true), (0.9, true)]);
+        let thresholds = sweep(&samples, 5);
+        assert_eq!(detect(&samples, thresholds[0]).recall, 1.0);
+        assert_eq!(detect(&samples, thresholds[0]).false_positive_rate, 1.0);
+        assert_eq!(detect(&samples, *thresholds.last().unwrap()).true_positives, 0);
+    }
+}
@@ -1,52 +1,325 @@
+mod backend;
+mod cli;
 mod data;
+mod evaluate;
+mod runs;
+mod schedule;
+mod search;
 mod training;
-use autoencoder::{data::{SyscallBatcher, Syscalls}, Autoencoder};
-use burn::{backend::{Autodiff, Wgpu}, config::Config, data::dataloader::{batcher::Batcher, Dataset}, module::Module, nn::loss::{self, MseLoss}, optim::AdamConfig, prelude::Backend, record::{CompactRecorder, Recorder}, tensor::cast::ToElement};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use anyhow::{bail, Context};
+use autoencoder::{
+    bundle::{Bundle, Manifest, BUNDLE},
+    calibration::{Calibration, Strategy},
+    data::Syscalls,
+    registry::{Provenance, Registry, Run},
+    signing::{self, sign_bundle},
+    Autoencoder, Model, ModelConfig,
+};
+use burn::{backend::Autodiff, config::Config, data::dataloader::Dataset, module::Module, optim::AdamConfig, prelude::Backend, record::{CompactRecorder, Recorder}};
+use clap::Parser;
+use rand::rngs::OsRng;
+use cli::{Cli, Command, KeygenArgs, TrainArgs};
 use data::SyscallsDataset;
-use training::{train, Model, ModelConfig, TrainingConfig};
+use ed25519_dalek::SigningKey;
+use ebsentinel_db::{EbsentinelDb, Metadata, DENSE_LAYOUT, RATE_MAX_NORM, SPARSE_LAYOUT, SPLITS};
+use sha2::{Digest, Sha256};
+use ebsentinel_features::{pipeline::Preprocessing, selection::{FeatureSelection, Selection}};
+use training::{train, FineTune, Replay, TrainingConfig, SELECTION};
 
+type MyBackend = backend::Backend;
+type MyAutodiffBackend = Autodiff<MyBackend>;
 
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    match cli.command {
+        Some(Command::Evaluate(args)) => evaluate::run(&args),
+        Some(Command::Search(args)) => search::run(&args),
+        Some(Command::Runs(args)) => runs::run(&args),
+        Some(Command::Keygen(args)) => keygen(&args),
+        None => train_model(cli.train),
+    }
+}
 
-fn main() {
-    type MyBackend = Wgpu<f32, i32>;
-    type MyAutodiffBackend = Autodiff<MyBackend>;
-    let device = burn::backend::wgpu::WgpuDevice::default();
+fn train_model(cli: TrainArgs) -> anyhow::Result<()> {
+    let (config, fine_tune) = load_training_config(&cli)?;
+    //A missing key is reported before the training, not after.
+    let signing_key = cli.signing_key.as_deref().map(signing::load_signing_key).transpose()?;
+    let mut registry = Registry::open(&cli.artifact_dir)?;
+    let mut tags = cli.tags.clone();
+    if let Some(fine_tune) = &fine_tune {
+        println!("fine-tuning the model of run {}", fine_tune.run);
+        tags.push("fine-tune".to_string());
+    }
+    let (mut run, dir) = registry.create_run(tags)?;
+    run.base = fine_tune.as_ref().map(|fine_tune| fine_tune.run.clone());
+    if let Err(e) = train_run(&cli, config, fine_tune.as_ref(), signing_key.as_ref(), &mut run, &dir.to_string_lossy()) {
+        discard(&registry, &run);
+        return Err(e);
+    }
+    println!("run {} saved in {}", run.name, dir.display());
+    registry.add(run)?;
 
-    let artifact_dir = "experiment";
+    println!();
 
-    train::<MyAutodiffBackend>(
-        "ebsentinel.db",
-        artifact_dir,
-        TrainingConfig::new(ModelConfig::new(512, 64), AdamConfig::new()),
-        device.clone(),
+    Ok(())
+}
+
+//Train, calibrate and bundle the model of `run` in `dir`.
+fn train_run(
+    cli: &TrainArgs,
+    config: TrainingConfig,
+    fine_tune: Option<&FineTune>,
+    signing_key: Option<&SigningKey>,
+    run: &mut Run,
+    dir: &str,
+) -> anyhow::Result<()> {
+    //The threshold of a fine-tuned model is calibrated again, on the new samples.
+    let calibration = fit(&cli.db_file, dir, config, fine_tune, cli.calibration, &cli.calibration_split)?;
+
+    println!(
+        "threshold: {} ({} on {} {} samples)",
+        calibration.threshold, calibration.strategy, calibration.scores.count, calibration.split
     );
-    
+    run.metrics.insert("threshold".to_string(), calibration.threshold as f64);
+    run.metrics.insert("valid_loss".to_string(), mean(&losses(&cli.db_file, dir, "valid")?));
+    let data = provenance(&cli.db_file)?;
+    write_bundle(&cli.db_file, dir, data.clone(), signing_key)?;
+    run.data = Some(data);
+    Ok(())
+}
+
+//A run that failed is not in the registry, its directory would never be pruned.
+fn discard(registry: &Registry, run: &Run) {
+    if let Err(e) = registry.discard(run) {
+        eprintln!("{e:#}");
+    }
+}
 
-  
+//The training config of the command line, checked against the samples it is trained on, with
+//the run it fine-tunes.
+fn load_training_config(cli: &TrainArgs) -> anyhow::Result<(TrainingConfig, Option<FineTune>)> {
+    //Opening the database also migrates it to the current schema.
+    let db = EbsentinelDb::new(&cli.db_file)?;
+    let metadata = db.metadata()?;
+    let base = cli.fine_tune.as_deref().map(|selector| base_run(cli, selector)).transpose()?;
+    let config = training_config(cli, metadata.feature_size, base.as_ref().map(|(config, _)| config))?;
+    let fine_tune = base.map(|(_, fine_tune)| fine_tune);
+    if let Some(selection) = fine_tune.as_ref().and_then(|fine_tune| fine_tune.selection.as_ref()) {
+        if selection.input_size != metadata.feature_size {
+            bail!("the features of the run are selected out of {} but the samples have {}", selection.input_size, metadata.feature_size);
+        }
+    }
+    if let Some(replay) = fine_tune.as_ref().and_then(|fine_tune| fine_tune.replay.as_ref()) {
+        let replay_db = EbsentinelDb::new(&replay.db_file)?;
+        if replay_db.metadata()?.feature_size != metadata.feature_size {
+            bail!("the samples of {} do not have the features of {}", replay.db_file, cli.db_file);
+        }
+        if config.preprocessing.is_none() && replay_db.missing_features("train")? > 0 {
+            bail!("{} has train samples recorded without features, they cannot be replayed", replay.db_file);
+        }
+    }
+    match &config.preprocessing {
+        Some(preprocessing) => println!("deriving features from the raw counters with {preprocessing}"),
+        None => {
+            if ![DENSE_LAYOUT, SPARSE_LAYOUT].contains(&metadata.feature_layout.as_str()) || metadata.preprocessing != RATE_MAX_NORM {
+                bail!(
+                    "cannot train on {} samples preprocessed with {}",
+                    metadata.feature_layout,
+                    metadata.preprocessing
+                );
+            }
+            for split in ["train", "valid", cli.calibration_split.as_str()] {
+                if db.missing_features(split)? > 0 {
+                    bail!("{split} has samples recorded without features, train on their raw counters with --preprocessing");
+                }
+            }
+        }
+    }
+    if metadata.syscall_table_hash != Metadata::current().syscall_table_hash {
+        println!(
+            "warning: samples were recorded on {} whose syscall numbers differ from this host",
+            metadata.arch
+        );
+    }
+    Ok((config, fine_tune))
+}
+
+//The config and the model of the run `selector` picks, to fine-tune.
+fn base_run(cli: &TrainArgs, selector: &str) -> anyhow::Result<(TrainingConfig, FineTune)> {
+    let registry = Registry::open(&cli.artifact_dir)?;
+    let run = registry.resolve(selector)?;
+    let dir = registry.dir(&run.name);
+    let config = TrainingConfig::load(dir.join("config.json")).with_context(|| format!("no model config in run {}", run.name))?;
+    let replay = match cli.replay {
+        Some(ratio) if ratio <= 0.0 || !ratio.is_finite() => bail!("the replay ratio has to be positive, not {ratio}"),
+        Some(ratio) => {
+            let db_file = match (&cli.replay_db, &run.data) {
+                (Some(db_file), _) => db_file.clone(),
+                (None, Some(data)) => data.db.clone(),
+                (None, None) => bail!("run {} does not record its data, pass --replay-db", run.name),
+            };
+            Some(Replay { db_file, ratio })
+        }
+        None => None,
+    };
+    let selection = load_selection(&dir.to_string_lossy())?;
+    Ok((config, FineTune { run: run.name.clone(), model: dir.join("model"), selection, replay }))
+}
+
+//The config of --config, of the fine-tuned run or the default one, with the options given on the
+//command line.
+fn training_config(cli: &TrainArgs, feature_size: usize, base: Option<&TrainingConfig>) -> anyhow::Result<TrainingConfig> {
+    let mut config = match (&cli.config, base) {
+        (Some(path), _) => TrainingConfig::load(path).with_context(|| format!("cannot load {}", path.display()))?,
+        (None, Some(base)) => base.clone(),
+        //The input of the model follows the size of the recorded vectors.
+        (None, None) => TrainingConfig::new(ModelConfig::new(feature_size, 64), AdamConfig::new()),
+    };
+    config.feature_selection = cli.feature_selection.unwrap_or(config.feature_selection);
+    //The features are selected again on the train samples, a fine-tuned model keeps the ones of its run.
+    if config.feature_selection != FeatureSelection::All && base.is_none() {
+        config.model.input_size = feature_size;
+    }
+    if config.feature_selection == FeatureSelection::All && config.model.input_size != feature_size {
+        bail!("the model takes {} inputs but the samples have {feature_size} features", config.model.input_size);
+    }
+    if let Some(path) = &cli.preprocessing {
+        let json = fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+        config.preprocessing = Some(serde_json::from_str::<Preprocessing>(&json)?);
+    }
+    config.model.latent_size = cli.latent_size.unwrap_or(config.model.latent_size);
+    config.model.hidden_size = cli.hidden_size.or(config.model.hidden_size);
+    config.num_epochs = cli.epochs.unwrap_or(config.num_epochs);
+    config.batch_size = cli.batch_size.unwrap_or(config.batch_size);
+    config.learning_rate = cli.learning_rate.unwrap_or(config.learning_rate);
+    config.lr_schedule = cli.lr_schedule.unwrap_or(config.lr_schedule);
+    config.warmup_epochs = cli.warmup_epochs.unwrap_or(config.warmup_epochs);
+    config.early_stopping_patience = cli.patience.or(config.early_stopping_patience);
+    config.keep_best |= cli.keep_best;
+    config.seed = cli.seed.unwrap_or(config.seed);
+    config.num_workers = cli.workers.unwrap_or(config.num_workers);
+    if let Some(base) = base {
+        let (model, base_model) = (&config.model, &base.model);
+        if (model.latent_size, model.hidden_size) != (base_model.latent_size, base_model.hidden_size)
+            || config.preprocessing != base.preprocessing
+            || config.feature_selection != base.feature_selection
+        {
+            bail!("a fine-tuned model keeps the layers, the preprocessing and the features of its run");
+        }
+    }
+    Ok(config)
+}
+
+//Trains a model into `artifact_dir`, then calibrates its threshold on `split`.
+fn fit(
+    db_file: &str,
+    artifact_dir: &str,
+    config: TrainingConfig,
+    fine_tune: Option<&FineTune>,
+    strategy: Strategy,
+    split: &str,
+) -> anyhow::Result<Calibration> {
+    train::<MyAutodiffBackend>(db_file, artifact_dir, config, fine_tune, backend::device())?;
+
+    //The threshold is calibrated on samples the model was not fitted on.
+    let scores = losses(db_file, artifact_dir, split)?;
+    let calibration = Calibration::new(strategy, split, &scores)?;
+    calibration.save(format!("{artifact_dir}/calibration.json"))?;
+    Ok(calibration)
+}
+
+//The model trained in `artifact_dir`, with its config.
+fn load_model(artifact_dir: &str) -> anyhow::Result<(TrainingConfig, Model<MyBackend>)> {
+    let device = backend::device();
     let config = TrainingConfig::load(format!("{artifact_dir}/config.json"))
-        .expect("Config should exist for the model");
+        .with_context(|| format!("no model config in {artifact_dir}"))?;
     let record = CompactRecorder::new()
         .load(format!("{artifact_dir}/model").into(), &device)
-        .expect("Trained model should exist");
-
+        .with_context(|| format!("no trained model in {artifact_dir}"))?;
     let model = config.model.init::<MyBackend>(&device).load_record(record);
+    Ok((config, model))
+}
 
-    let dataset=SyscallsDataset::train("ebsentinel.db");
+//Packs the model trained and calibrated in `artifact_dir` into the bundle the detector loads,
+//signed with `signing_key` if given.
+fn write_bundle(db_file: &str, artifact_dir: &str, data: Provenance, signing_key: Option<&SigningKey>) -> anyhow::Result<()> {
+    let metadata = EbsentinelDb::new(db_file)?.metadata()?;
+    let (config, model) = load_model(artifact_dir)?;
+    let manifest = Manifest {
+        model: config.model,
+        preprocessing: config.preprocessing,
+        selection: load_selection(artifact_dir)?,
+        calibration: Calibration::load(format!("{artifact_dir}/calibration.json"))?,
+        arch: metadata.arch,
+        syscall_table_hash: metadata.syscall_table_hash,
+        data: Some(data),
+    };
+    let path = Path::new(artifact_dir).join(BUNDLE);
+    Bundle { manifest, model }.save(&path)?;
+    if let Some(key) = signing_key {
+        let signature = sign_bundle(&path, key)?;
+        println!("model signed with key {}", signature.key_id);
+    }
+    Ok(())
+}
 
-    let mut thres: f32=0.0;
+fn keygen(args: &KeygenArgs) -> anyhow::Result<()> {
+    let key = SigningKey::generate(&mut OsRng);
+    let public = signing::write_keypair(&key, &args.output)?;
+    println!(
+        "key {} written to {}, give {} to ebsentinel --trusted-key",
+        signing::key_id(&key.verifying_key()),
+        args.output.display(),
+        public.display()
+    );
+    Ok(())
+}
 
-    for i in 0..dataset.len(){
-        let item=dataset.get(i).unwrap();
-        let (_,loss)=infer::<MyBackend>(device.clone(),&model,item.clone() );
-        thres= thres.max(loss);
+//The features selected for the model trained in `artifact_dir`, None if it takes them all.
+fn load_selection(artifact_dir: &str) -> anyhow::Result<Option<Selection>> {
+    let path = Path::new(artifact_dir).join(SELECTION);
+    match fs::read_to_string(&path) {
+        Ok(json) => Ok(Some(serde_json::from_str(&json).with_context(|| format!("invalid selection {}", path.display()))?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("cannot read {}", path.display())),
     }
-    
-    println!("threshold: {}",thres);
+}
 
-    println!();
+//Reconstruction losses of the samples of `split` with the model trained in `artifact_dir`.
+fn losses(db_file: &str, artifact_dir: &str, split: &str) -> anyhow::Result<Vec<f32>> {
+    let device = backend::device();
+    let (config, model) = load_model(artifact_dir)?;
+    let mut dataset = SyscallsDataset::load(db_file, split, config.preprocessing.as_ref())?;
+    if let Some(selection) = load_selection(artifact_dir)? {
+        dataset = dataset.select(selection);
+    }
+    Ok((0..dataset.len())
+        .map(|i| infer::<MyBackend>(device.clone(), &model, dataset.get(i).unwrap()).1)
+        .collect())
+}
 
+fn mean(losses: &[f32]) -> f64 {
+    losses.iter().map(|loss| *loss as f64).sum::<f64>() / losses.len().max(1) as f64
+}
+
+//What a run was trained on, to tell later which data a model has seen.
+fn provenance(db_file: &str) -> anyhow::Result<Provenance> {
+    let db = EbsentinelDb::new(db_file)?;
+    let metadata = db.metadata()?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(db_file)?, &mut hasher)?;
+    Ok(Provenance {
+        db: fs::canonicalize(db_file)?.to_string_lossy().into_owned(),
+        sha256: format!("{:x}", hasher.finalize()),
+        samples: SPLITS.iter().map(|split| split.to_string()).zip(db.split_sizes()?).collect(),
+        sessions: db.sessions()?.into_iter().map(|stored| stored.session.name).collect(),
+        preprocessing: metadata.preprocessing,
+        syscall_table_hash: metadata.syscall_table_hash,
+    })
 }
 
 pub fn infer<B: Backend>(device: B::Device, model: &Model<B>, item: Syscalls) -> (Vec<f32>,f32) {
     Autoencoder::infer(device,&model.inner,item)
-}
\ No newline at end of file
+}
@@ -0,0 +1,121 @@
+use std::{collections::BTreeMap, fs};
+
+use anyhow::Context;
+use autoencoder::{
+    bundle::BUNDLE,
+    registry::{utc, Registry, Run},
+    signing::{load_signing_key, sign_bundle},
+};
+use serde_json::Value;
+
+use crate::cli::{RunsArgs, RunsCommand};
+
+pub fn run(args: &RunsArgs) -> anyhow::Result<()> {
+    let mut registry = Registry::open(&args.artifact_dir)?;
+    match &args.command {
+        RunsCommand::List => list(&registry),
+        RunsCommand::Compare { runs } => compare(&registry, runs)?,
+        RunsCommand::Promote { run } => {
+            let name = registry.promote(run)?;
+            println!("{name} is now the default model");
+        }
+        RunsCommand::Sign { run, key } => {
+            let run = registry.resolve(run)?;
+            let signature = sign_bundle(&registry.dir(&run.name).join(BUNDLE), &load_signing_key(key)?)?;
+            println!("{} signed with key {}", run.name, signature.key_id);
+        }
+        RunsCommand::Prune { keep, keep_tagged } => {
+            for name in registry.prune(*keep, *keep_tagged)? {
+                println!("removed {name}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list(registry: &Registry) {
+    println!("{:<22}{:<20}{:>10}  {:<24}metrics", "run", "created", "samples", "tags");
+    for run in registry.runs() {
+        let name = if registry.promoted() == Some(run.name.as_str()) { format!("{} *", run.name) } else { run.name.clone() };
+        let samples = run.data.as_ref().map_or("-".to_string(), |data| data.samples.values().sum::<usize>().to_string());
+        let metrics: Vec<String> = run.metrics.iter().map(|(metric, value)| format!("{metric}={value:.4}")).collect();
+        println!("{name:<22}{:<20}{samples:>10}  {:<24}{}", utc(run.created_at), run.tags.join(","), metrics.join(" "));
+    }
+    if registry.promoted().is_some() {
+        println!("* promoted");
+    }
+}
+
+fn compare(registry: &Registry, selectors: &[String]) -> anyhow::Result<()> {
+    let runs = selectors.iter().map(|selector| registry.resolve(selector)).collect::<anyhow::Result<Vec<&Run>>>()?;
+    let mut fields = Vec::with_capacity(runs.len());
+    for run in &runs {
+        let path = registry.dir(&run.name).join("config.json");
+        let config: Value = serde_json::from_str(&fs::read_to_string(&path).with_context(|| format!("cannot read {}", path.display()))?)?;
+        let mut flat = BTreeMap::new();
+        flat.insert("base".to_string(), run.base.clone().unwrap_or_else(|| "-".to_string()));
+        flatten("data", &serde_json::to_value(&run.data)?, &mut flat);
+        flatten("config", &config, &mut flat);
+        for (metric, value) in &run.metrics {
+            flat.insert(format!("metrics.{metric}"), format!("{value:.6}"));
+        }
+        fields.push(flat);
+    }
+
+    println!("{:<40}{}", "", runs.iter().map(|run| format!("{:<24}", run.name)).collect::<String>());
+    for key in differing(&fields) {
+        let values: String = fields.iter().map(|flat| format!("{:<24}", flat.get(&key).map_or("-", String::as_str))).collect();
+        println!("{key:<40}{values}");
+    }
+    Ok(())
+}
+
+//Leaf values of a JSON document, keyed by their dotted path.
+fn flatten(prefix: &str, value: &Value, flat: &mut BTreeMap<String, String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                flatten(&format!("{prefix}.{key}"), value, flat);
+            }
+        }
+        Value::String(s) => {
+            flat.insert(prefix.to_string(), s.clone());
+        }
+        _ => {
+            flat.insert(prefix.to_string(), value.to_string());
+        }
+    }
+}
+
+//The keys whose value is not the same in every run, metrics are always shown.
+fn differing(fields: &[BTreeMap<String, String>]) -> Vec<String> {
+    let mut keys: Vec<&String> = fields.iter().flat_map(BTreeMap::keys).collect();
+    keys.sort();
+    keys.dedup();
+    keys.into_iter()
+        .filter(|key| key.starts_with("metrics.") || fields.iter().any(|flat| flat.get(*key) != fields[0].get(*key)))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use serde_json::json;
+
+    use super::{differing, flatten};
+
+    #[test]
+    fn shows_differing_fields() {
+        let mut fields = Vec::new();
+        for latent_size in [32, 64] {
+            let mut flat = BTreeMap::new();
+            flatten("config", &json!({"model": {"input_size": 456, "latent_size": latent_size}, "seed": 42}), &mut flat);
+            flat.insert("metrics.threshold".to_string(), "0.1".to_string());
+            fields.push(flat);
+        }
+        assert_eq!(fields[0]["config.model.latent_size"], "32");
+        assert_eq!(differing(&fields), ["config.model.latent_size", "metrics.threshold"]);
+    }
+}
@@ -0,0 +1,140 @@
+use std::{f64::consts::PI, fmt, str::FromStr};
+
+use anyhow::anyhow;
+use burn::{lr_scheduler::LrScheduler, prelude::Backend, LearningRate};
+use serde::{Deserialize, Serialize};
+
+/// How the learning rate decays from its initial value over the training.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "schedule", rename_all = "kebab-case")]
+pub enum LrSchedule {
+    Constant,
+    /// Cosine annealing down to `min_lr` at the last epoch.
+    Cosine { min_lr: f64 },
+    /// The learning rate is multiplied by `gamma` every `epochs` epochs.
+    Step { epochs: usize, gamma: f64 },
+}
+
+impl FromStr for LrSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let schedule = match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("constant"), None, ..) => Self::Constant,
+            (Some("cosine"), None, ..) => Self::Cosine { min_lr: 0.0 },
+            (Some("cosine"), Some(min_lr), None, _) => Self::Cosine { min_lr: min_lr.parse()? },
+            (Some("step"), Some(epochs), Some(gamma), None) => Self::Step { epochs: epochs.parse()?, gamma: gamma.parse()? },
+            _ => return Err(anyhow!("unknown schedule {s}, expected constant, cosine[:MIN_LR] or step:EPOCHS:GAMMA")),
+        };
+        if matches!(schedule, Self::Step { epochs: 0, .. }) {
+            return Err(anyhow!("the step schedule decays every 1 epoch or more"));
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for LrSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Constant => f.write_str("constant"),
+            Self::Cosine { min_lr } => write!(f, "cosine:{min_lr}"),
+            Self::Step { epochs, gamma } => write!(f, "step:{epochs}:{gamma}"),
+        }
+    }
+}
+
+/// Learning rate of every optimizer step: a linear warmup, then the schedule.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    learning_rate: f64,
+    schedule: LrSchedule,
+    iterations_per_epoch: usize,
+    warmup: usize,
+    total: usize,
+    iteration: usize,
+}
+
+impl Scheduler {
+    pub fn new(learning_rate: f64, schedule: LrSchedule, warmup_epochs: usize, num_epochs: usize, iterations_per_epoch: usize) -> Self {
+        let iterations_per_epoch = iterations_per_epoch.max(1);
+        Self {
+            learning_rate,
+            schedule,
+            iterations_per_epoch,
+            warmup: warmup_epochs * iterations_per_epoch,
+            total: num_epochs * iterations_per_epoch,
+            iteration: 0,
+        }
+    }
+
+    fn rate(&self, iteration: usize) -> f64 {
+        if iteration < self.warmup {
+            return self.learning_rate * (iteration + 1) as f64 / self.warmup as f64;
+        }
+        //The schedule starts once the warmup is over.
+        let iteration = iteration - self.warmup;
+        match self.schedule {
+            LrSchedule::Constant => self.learning_rate,
+            LrSchedule::Cosine { min_lr } => {
+                let progress = iteration as f64 / self.total.saturating_sub(self.warmup).max(1) as f64;
+                min_lr + (self.learning_rate - min_lr) * (1.0 + (PI * progress.min(1.0)).cos()) / 2.0
+            }
+            LrSchedule::Step { epochs, gamma } => {
+                let decays = iteration / self.iterations_per_epoch / epochs;
+                self.learning_rate * gamma.powi(decays as i32)
+            }
+        }
+    }
+}
+
+impl LrScheduler for Scheduler {
+    //Resuming from a checkpoint only needs the number of steps taken.
+    type Record<B: Backend> = usize;
+
+    fn step(&mut self) -> LearningRate {
+        let rate = self.rate(self.iteration);
+        self.iteration += 1;
+        rate
+    }
+
+    fn to_record<B: Backend>(&self) -> Self::Record<B> {
+        self.iteration
+    }
+
+    fn load_record<B: Backend>(mut self, record: Self::Record<B>) -> Self {
+        self.iteration = record;
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LrSchedule, Scheduler};
+
+    #[test]
+    fn parses_schedules() {
+        for s in ["constant", "cosine:0.00001", "step:10:0.5"] {
+            assert_eq!(s.parse::<LrSchedule>().unwrap().to_string(), s);
+        }
+        assert_eq!("cosine".parse::<LrSchedule>().unwrap(), LrSchedule::Cosine { min_lr: 0.0 });
+        assert!("step:0:0.5".parse::<LrSchedule>().is_err());
+        assert!("linear".parse::<LrSchedule>().is_err());
+    }
+
+    #[test]
+    fn warmup_then_schedule() {
+        //2 warmup epochs of 10 steps, then 8 epochs of cosine annealing.
+        let scheduler = Scheduler::new(1.0, LrSchedule::Cosine { min_lr: 0.0 }, 2, 10, 10);
+        assert_eq!(scheduler.rate(0), 0.05);
+        assert_eq!(scheduler.rate(19), 1.0);
+        assert_eq!(scheduler.rate(20), 1.0);
+        assert!((scheduler.rate(60) - 0.5).abs() < 1e-9);
+        assert!(scheduler.rate(99) < 0.001);
+
+        let scheduler = Scheduler::new(1.0, LrSchedule::Step { epochs: 3, gamma: 0.5 }, 0, 10, 4);
+        assert_eq!(scheduler.rate(11), 1.0);
+        assert_eq!(scheduler.rate(12), 0.5);
+        assert_eq!(scheduler.rate(24), 0.25);
+    }
+}
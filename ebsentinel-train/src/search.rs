@@ -0,0 +1,299 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{bail, Context};
+use autoencoder::{
+    registry::{Registry, Run},
+    signing,
+};
+use clap::ValueEnum;
+use ed25519_dalek::SigningKey;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::{EvaluateArgs, Rank, SearchArgs, SearchStrategy},
+    discard, evaluate, fit, load_training_config, losses, mean, provenance,
+    training::{FineTune, TrainingConfig},
+    write_bundle,
+};
+
+/// A field of the training config the search can vary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    LatentSize,
+    HiddenSize,
+    LearningRate,
+    BatchSize,
+    NumEpochs,
+}
+
+impl Field {
+    fn name(self) -> &'static str {
+        match self {
+            Self::LatentSize => "latent_size",
+            Self::HiddenSize => "hidden_size",
+            Self::LearningRate => "learning_rate",
+            Self::BatchSize => "batch_size",
+            Self::NumEpochs => "num_epochs",
+        }
+    }
+
+    fn apply(self, config: &mut TrainingConfig, value: f64) -> anyhow::Result<()> {
+        let size = || {
+            if value < 1.0 || value.fract() != 0.0 {
+                bail!("{} takes positive integers, not {value}", self.name());
+            }
+            Ok(value as usize)
+        };
+        match self {
+            Self::LatentSize => config.model.latent_size = size()?,
+            Self::HiddenSize => config.model.hidden_size = Some(size()?),
+            Self::BatchSize => config.batch_size = size()?,
+            Self::NumEpochs => config.num_epochs = size()?,
+            Self::LearningRate if value > 0.0 => config.learning_rate = value,
+            Self::LearningRate => bail!("the learning rate has to be positive, not {value}"),
+        }
+        Ok(())
+    }
+
+    fn integer(self) -> bool {
+        self != Self::LearningRate
+    }
+}
+
+/// Values a field takes in the search.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Values {
+    List(Vec<f64>),
+    /// Only for random search, drawn uniformly, or log-uniformly, between `low` and `high`.
+    Range {
+        low: f64,
+        high: f64,
+        #[serde(default)]
+        log: bool,
+    },
+}
+
+/// Values of each searched field, the others keep the value of the base config.
+pub type Space = BTreeMap<Field, Values>;
+pub type Params = BTreeMap<Field, f64>;
+
+/// Every combination of the listed values.
+pub fn grid(space: &Space) -> anyhow::Result<Vec<Params>> {
+    let mut trials = vec![Params::new()];
+    for (field, values) in space {
+        let Values::List(values) = values else {
+            bail!("grid search takes lists of values, {} has a range", field.name());
+        };
+        trials = trials
+            .into_iter()
+            .flat_map(|params| {
+                values.iter().map(move |value| {
+                    let mut params = params.clone();
+                    params.insert(*field, *value);
+                    params
+                })
+            })
+            .collect();
+    }
+    Ok(trials)
+}
+
+/// `trials` random points of the space.
+pub fn random(space: &Space, trials: usize, seed: u64) -> anyhow::Result<Vec<Params>> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut draws = Vec::with_capacity(trials);
+    for _ in 0..trials {
+        let mut params = Params::new();
+        for (field, values) in space {
+            let value = match *values {
+                Values::List(ref values) => *values.choose(&mut rng).with_context(|| format!("{} has no value", field.name()))?,
+                Values::Range { low, high, log } => {
+                    if low >= high || (log && low <= 0.0) {
+                        bail!("invalid range of {}: {low} to {high}", field.name());
+                    }
+                    let value = if log { rng.gen_range(low.ln()..high.ln()).exp() } else { rng.gen_range(low..high) };
+                    if field.integer() { value.round() } else { value }
+                }
+            };
+            params.insert(*field, value);
+        }
+        draws.push(params);
+    }
+    Ok(draws)
+}
+
+#[derive(Debug, Serialize)]
+pub struct Trial {
+    pub name: String,
+    pub params: Params,
+    pub valid_loss: f64,
+    pub threshold: f32,
+    /// The metric of --rank, unless the trials are ranked by their valid loss.
+    pub metric: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    rank: &'a str,
+    best: &'a str,
+    /// Best first.
+    trials: &'a [Trial],
+}
+
+/// Train every trial of the search in a subdirectory of a new run, then keep the best one as the model of the run.
+pub fn run(args: &SearchArgs) -> anyhow::Result<()> {
+    if args.rank != Rank::ValidLoss && args.eval_db.is_none() {
+        bail!("ranking the trials by a metric needs labeled samples, pass --eval-db");
+    }
+    let json = fs::read_to_string(&args.space).with_context(|| format!("cannot read {}", args.space.display()))?;
+    let space: Space = serde_json::from_str(&json).with_context(|| format!("invalid search space {}", args.space.display()))?;
+    let points = match args.strategy {
+        SearchStrategy::Grid => grid(&space)?,
+        SearchStrategy::Random => random(&space, args.trials, args.search_seed)?,
+    };
+    let (base, fine_tune) = load_training_config(&args.train)?;
+    if fine_tune.is_some() && space.keys().any(|field| matches!(field, Field::LatentSize | Field::HiddenSize)) {
+        bail!("a fine-tuned model keeps the layers of its run, latent_size and hidden_size cannot be searched");
+    }
+    let signing_key = args.train.signing_key.as_deref().map(signing::load_signing_key).transpose()?;
+
+    let mut registry = Registry::open(&args.train.artifact_dir)?;
+    let mut tags = args.train.tags.clone();
+    tags.push("search".to_string());
+    let (mut run, run_dir) = registry.create_run(tags)?;
+    run.base = fine_tune.as_ref().map(|fine_tune| fine_tune.run.clone());
+    if let Err(e) = search(args, points, &base, fine_tune.as_ref(), signing_key.as_ref(), &mut run, &run_dir) {
+        discard(&registry, &run);
+        return Err(e);
+    }
+    println!("run {} saved in {}", run.name, run_dir.display());
+    registry.add(run)?;
+    Ok(())
+}
+
+//Train every trial in `run_dir`, rank them and bundle the best one as the model of `run`.
+fn search(
+    args: &SearchArgs,
+    points: Vec<Params>,
+    base: &TrainingConfig,
+    fine_tune: Option<&FineTune>,
+    signing_key: Option<&SigningKey>,
+    run: &mut Run,
+    run_dir: &Path,
+) -> anyhow::Result<()> {
+    let db_file = args.train.db_file.as_str();
+    let trials_dir = run_dir.join("trials");
+    let total = points.len();
+    let mut trials = Vec::with_capacity(total);
+    for (i, params) in points.into_iter().enumerate() {
+        let name = format!("trial-{i:03}");
+        let dir = trials_dir.join(&name).to_string_lossy().into_owned();
+        let mut config = base.clone();
+        for (field, value) in &params {
+            field.apply(&mut config, *value)?;
+        }
+        println!("{name} ({}/{}): {}", i + 1, total, describe(&params));
+
+        let calibration = fit(db_file, &dir, config, fine_tune, args.train.calibration, &args.train.calibration_split)?;
+        let valid_loss = mean(&losses(db_file, &dir, "valid")?);
+        let metric = match &args.eval_db {
+            Some(eval_db) if args.rank != Rank::ValidLoss => {
+                let evaluation = evaluate::evaluate(&EvaluateArgs {
+                    db_file: eval_db.clone(),
+                    artifact_dir: dir.clone(),
+                    model: None,
+                    split: args.eval_split.clone(),
+                    benign_labels: args.benign_labels.clone(),
+                    threshold: None,
+                    sweep: 20,
+                    output: None,
+                })?;
+                evaluate::write(&evaluation, Path::new(&dir))?;
+                let metric = match args.rank {
+                    Rank::RocAuc => evaluation.roc_auc,
+                    Rank::PrAuc => evaluation.pr_auc,
+                    Rank::F1 => Some(evaluation.at_threshold.f1),
+                    Rank::ValidLoss => unreachable!(),
+                };
+                Some(metric.context("ROC-AUC and PR-AUC need both benign and attack samples in --eval-db")?)
+            }
+            _ => None,
+        };
+        println!("{name}: valid loss {valid_loss:.6}{}", metric.map_or(String::new(), |metric| format!(", metric {metric:.4}")));
+        trials.push(Trial { name, params, valid_loss, threshold: calibration.threshold, metric });
+    }
+    if trials.is_empty() {
+        bail!("the search space has no point");
+    }
+
+    //Lowest loss, or highest metric, first.
+    trials.sort_by(|a, b| match (a.metric, b.metric) {
+        (Some(a), Some(b)) => b.total_cmp(&a),
+        _ => a.valid_loss.total_cmp(&b.valid_loss),
+    });
+    let best = &trials[0];
+    copy_model(&trials_dir.join(&best.name), run_dir)?;
+    let data = provenance(db_file)?;
+    write_bundle(db_file, &run_dir.to_string_lossy(), data.clone(), signing_key)?;
+    let rank = args.rank.to_possible_value().unwrap();
+    let report = Report { rank: rank.get_name(), best: &best.name, trials: &trials };
+    fs::write(run_dir.join("search.json"), serde_json::to_string_pretty(&report)?)?;
+
+    run.metrics.insert("threshold".to_string(), best.threshold as f64);
+    run.metrics.insert("valid_loss".to_string(), best.valid_loss);
+    if let Some(metric) = best.metric {
+        run.metrics.insert(rank.get_name().replace('-', "_"), metric);
+    }
+    run.data = Some(data);
+    println!("best: {} ({})", best.name, describe(&best.params));
+    Ok(())
+}
+
+fn describe(params: &Params) -> String {
+    params.iter().map(|(field, value)| format!("{}={value}", field.name())).collect::<Vec<_>>().join(" ")
+}
+
+//Copy the model, its config and its calibration to the run, the trials stay where they are.
+fn copy_model(trial_dir: &Path, run_dir: &Path) -> anyhow::Result<()> {
+    for entry in fs::read_dir(trial_dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            fs::copy(entry.path(), run_dir.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{grid, random, Field, Space};
+
+    fn space(json: &str) -> Space {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn grid_tries_every_combination() {
+        let trials = grid(&space(r#"{"latent_size": [16, 32, 64], "learning_rate": [0.001, 0.0001]}"#)).unwrap();
+        assert_eq!(trials.len(), 6);
+        assert_eq!(trials[5][&Field::LatentSize], 64.0);
+        assert!(grid(&space(r#"{"learning_rate": {"low": 0.0001, "high": 0.01}}"#)).is_err());
+        assert!(serde_json::from_str::<Space>(r#"{"layers": [1]}"#).is_err());
+    }
+
+    #[test]
+    fn random_draws_within_ranges() {
+        let space = space(r#"{"hidden_size": {"low": 32, "high": 256}, "learning_rate": {"low": 1e-5, "high": 1e-2, "log": true}}"#);
+        let trials = random(&space, 50, 7).unwrap();
+        assert_eq!(trials.len(), 50);
+        for params in &trials {
+            let hidden = params[&Field::HiddenSize];
+            assert!((32.0..=256.0).contains(&hidden) && hidden.fract() == 0.0);
+            assert!((1e-5..1e-2).contains(&params[&Field::LearningRate]));
+        }
+        assert_eq!(random(&space, 5, 7).unwrap(), trials[..5]);
+    }
+}
@@ -1,69 +1,23 @@
-use std::vec;
+use std::{fs, path::{Path, PathBuf}, vec};
 
-use autoencoder::{data::{SyscallBatch, SyscallBatcher}, Autoencoder, AutoencoderConfig};
+use anyhow::Context;
+use autoencoder::{data::SyscallBatcher, Model, ModelConfig};
 use burn::{
-    data::dataloader::DataLoaderBuilder,
-    nn::loss::MseLoss,
+    data::dataloader::{DataLoaderBuilder, Dataset},
     optim::AdamConfig,
     prelude::*,
-    record::CompactRecorder,
-    tensor::backend::AutodiffBackend,
+    record::{CompactRecorder, Recorder},
+    tensor::{backend::AutodiffBackend, cast::ToElement},
     train::{
-        metric::LossMetric,
-        LearnerBuilder, RegressionOutput, TrainOutput, TrainStep, ValidStep,
+        metric::{store::{Aggregate, Direction, Split}, LossMetric},
+        LearnerBuilder, MetricEarlyStoppingStrategy, StoppingCondition,
     },
 };
 
-use crate::data::SyscallsDataset;
+use ebsentinel_features::{pipeline::Preprocessing, selection::{FeatureSelection, Selection}};
 
-use burn::tensor::{backend::Backend, Tensor};
+use crate::{data::SyscallsDataset, schedule::{LrSchedule, Scheduler}};
 
-#[derive(Module,Debug)]
-pub struct Model<B: Backend>{
-    pub inner: Autoencoder<B>
-}
-
-impl<B: Backend> Model<B> {
-    pub fn forward_reconstruction(&self, vecs: Tensor<B, 2>) -> RegressionOutput<B> {
-        let output = self.inner.forward(vecs.clone());
-        let loss: Tensor<B, 1> =
-            MseLoss::new().forward( output.clone(),vecs.clone(), nn::loss::Reduction::Mean);
-        RegressionOutput::new(loss, output, vecs)
-    }
-}
-
-impl<B: Backend> Model<B> {
-    pub fn forward(&self, vecs: Tensor<B, 2>) -> Tensor<B,2> {
-       self.inner.forward(vecs)
-    }
-}
-
-impl<B: AutodiffBackend> TrainStep<SyscallBatch<B>, RegressionOutput<B>> for Model<B> {
-    fn step(&self, batch: SyscallBatch<B>) -> TrainOutput<RegressionOutput<B>> {
-        let item = self.forward_reconstruction(batch.syscalls);
-        TrainOutput::new(self, item.loss.backward(), item)
-    }
-}
-
-
-impl<B: Backend> ValidStep<SyscallBatch<B>, RegressionOutput<B>> for Model<B> {
-    fn step(&self, batch: SyscallBatch<B>) -> RegressionOutput<B> {
-        self.forward_reconstruction(batch.syscalls)
-    }
-}
-
-#[derive(Config, Debug)]
-pub struct ModelConfig{
-    input_size: usize, 
-    latent_size: usize
-}
-
-impl ModelConfig {
-    pub fn init<B: Backend>(&self, device: &B::Device) -> Model<B> {
-        let inner=AutoencoderConfig::new(self.input_size,self.latent_size).init(device);
-        Model { inner }
-    }
-}
 #[derive(Config)]
 pub struct TrainingConfig {
     pub model: ModelConfig,
@@ -78,21 +32,74 @@ pub struct TrainingConfig {
     pub seed: u64,
     #[config(default = 0.0001414213562373095)]
     pub learning_rate: f64,
+    /// How the learning rate decays after the warmup.
+    #[config(default = "LrSchedule::Constant")]
+    pub lr_schedule: LrSchedule,
+    /// Epochs over which the learning rate first rises linearly to `learning_rate`.
+    #[config(default = 0)]
+    pub warmup_epochs: usize,
+    /// Stop once the valid loss has not improved for this many epochs.
+    pub early_stopping_patience: Option<usize>,
+    /// Keep the model of the epoch with the lowest valid loss instead of the last one.
+    #[config(default = false)]
+    pub keep_best: bool,
+    /// Features derived from the raw counters instead of the recorded ones.
+    pub preprocessing: Option<Preprocessing>,
+    /// Features the model takes, chosen on the train samples and saved in `selection.json`.
+    #[config(default = "FeatureSelection::All")]
+    pub feature_selection: FeatureSelection,
 }
 
-fn create_artifact_dir(artifact_dir: &str) {
-    // Remove existing artifacts before to get an accurate learner summary
-    std::fs::remove_dir_all(artifact_dir).ok();
-    std::fs::create_dir_all(artifact_dir).ok();
+/// Features kept by the feature selection of a run, absent if it kept them all.
+pub const SELECTION: &str = "selection.json";
+
+/// Weights a training starts from instead of fresh ones.
+pub struct FineTune {
+    /// Run the weights come from.
+    pub run: String,
+    /// Model record of the run, without its extension.
+    pub model: PathBuf,
+    /// The features the model of the run takes, a fine-tuned model keeps them.
+    pub selection: Option<Selection>,
+    pub replay: Option<Replay>,
+}
+
+/// Samples of older data mixed into the training samples, so that a fine-tuned model does not forget them.
+pub struct Replay {
+    pub db_file: String,
+    /// Old samples per new sample.
+    pub ratio: f64,
+}
+
+fn create_artifact_dir(artifact_dir: &str) -> anyhow::Result<()> {
+    //Every run gets a directory of its own, a model is never trained over another one.
+    if fs::read_dir(artifact_dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        anyhow::bail!("{artifact_dir} is not empty");
+    }
+    fs::create_dir_all(artifact_dir)?;
+    Ok(())
 }
 
 pub fn train<B: AutodiffBackend>(
     db_file: &str,
     artifact_dir: &str,
-    config: TrainingConfig,
+    mut config: TrainingConfig,
+    fine_tune: Option<&FineTune>,
     device: B::Device,
-) {
-    create_artifact_dir(artifact_dir);
+) -> anyhow::Result<()> {
+    create_artifact_dir(artifact_dir)?;
+
+    let mut train_dataset = SyscallsDataset::load(db_file, "train", config.preprocessing.as_ref())?;
+    let mut valid_dataset = SyscallsDataset::load(db_file, "valid", config.preprocessing.as_ref())?;
+    let selection = match fine_tune {
+        Some(fine_tune) => fine_tune.selection.clone(),
+        None => config.feature_selection.fit((0..train_dataset.len()).filter_map(|i| train_dataset.get(i)).map(|item| item.counts))?,
+    };
+    if let Some(selection) = &selection {
+        println!("keeping {} of {} features ({})", selection.kept.len(), selection.input_size, config.feature_selection);
+        fs::write(Path::new(artifact_dir).join(SELECTION), serde_json::to_string_pretty(selection)?)?;
+        config.model.input_size = selection.kept.len();
+    }
 
     config
         .save(format!("{artifact_dir}/config.json"))
@@ -103,8 +110,28 @@ pub fn train<B: AutodiffBackend>(
     let batcher_train = SyscallBatcher::<B>::new(device.clone());
     let batcher_valid = SyscallBatcher::<B::InnerBackend>::new(device.clone());
 
-    let train_dataset = SyscallsDataset::train(db_file);
-    let test_dataset = SyscallsDataset::test(db_file);
+    if let Some(replay) = fine_tune.and_then(|fine_tune| fine_tune.replay.as_ref()) {
+        let old = SyscallsDataset::load(&replay.db_file, "train", config.preprocessing.as_ref())?;
+        if old.is_empty() {
+            anyhow::bail!("no train sample to replay in {}", replay.db_file);
+        }
+        let count = (train_dataset.len() as f64 * replay.ratio).round() as usize;
+        println!("replaying {count} train samples of {}", replay.db_file);
+        train_dataset = train_dataset.with_replay(&old, count, config.seed);
+    }
+    if let Some(selection) = &selection {
+        train_dataset = train_dataset.select(selection.clone());
+        valid_dataset = valid_dataset.select(selection.clone());
+    }
+
+    let iterations_per_epoch = train_dataset.len().div_ceil(config.batch_size);
+    let scheduler = Scheduler::new(
+        config.learning_rate,
+        config.lr_schedule,
+        config.warmup_epochs,
+        config.num_epochs,
+        iterations_per_epoch,
+    );
 
     let dataloader_train = DataLoaderBuilder::new(batcher_train)
         .batch_size(config.batch_size)
@@ -112,28 +139,98 @@ pub fn train<B: AutodiffBackend>(
         .num_workers(config.num_workers)
         .build(train_dataset);
 
-    let dataloader_test = DataLoaderBuilder::new(batcher_valid)
+    let dataloader_valid = DataLoaderBuilder::new(batcher_valid.clone())
         .batch_size(config.batch_size)
         .shuffle(config.seed)
         .num_workers(config.num_workers)
-        .build(test_dataset);
+        .build(valid_dataset);
 
-    let learner = LearnerBuilder::new(artifact_dir)
+    //The default checkpointing keeps the last two epochs and the one with the lowest valid loss.
+    let mut builder = LearnerBuilder::new(artifact_dir)
         .metric_train_numeric(LossMetric::new())
         .metric_valid_numeric(LossMetric::new())
         .with_file_checkpointer(CompactRecorder::new())
         .devices(vec![device.clone()])
         .num_epochs(config.num_epochs)
-        .summary()
-        .build(
-            config.model.init::<B>(&device),
-            config.optimizer.init(),
-            config.learning_rate,
-        );
-
-    let model_trained:Model<B> = learner.fit(dataloader_train, dataloader_test);
-
-    model_trained
-        .save_file(format!("{artifact_dir}/model"), &CompactRecorder::new())
-        .expect("Trained model should be saved successfully");
+        .summary();
+    if let Some(patience) = config.early_stopping_patience {
+        builder = builder.early_stopping(MetricEarlyStoppingStrategy::new::<LossMetric<B>>(
+            Aggregate::Mean,
+            Direction::Lowest,
+            Split::Valid,
+            StoppingCondition::NoImprovementSince { n_epochs: patience },
+        ));
+    }
+    let mut model = config.model.init::<B>(&device);
+    if let Some(fine_tune) = fine_tune {
+        let record = CompactRecorder::new()
+            .load(fine_tune.model.clone(), &device)
+            .with_context(|| format!("no trained model in run {}", fine_tune.run))?;
+        model = model.load_record(record);
+    }
+    let learner = builder.build(
+        model,
+        config.optimizer.init(),
+        scheduler,
+    );
+
+    let model_trained:Model<B> = learner.fit(dataloader_train, dataloader_valid);
+
+    if config.keep_best {
+        let mut valid_dataset = SyscallsDataset::load(db_file, "valid", config.preprocessing.as_ref())?;
+        if let Some(selection) = selection {
+            valid_dataset = valid_dataset.select(selection);
+        }
+        let (epoch, loss, model) = best_checkpoint::<B::InnerBackend>(artifact_dir, &config, valid_dataset, batcher_valid, &device)?;
+        println!("keeping the model of epoch {epoch}, valid loss {loss}");
+        model
+            .save_file(format!("{artifact_dir}/model"), &CompactRecorder::new())
+            .expect("Trained model should be saved successfully");
+    } else {
+        model_trained
+            .save_file(format!("{artifact_dir}/model"), &CompactRecorder::new())
+            .expect("Trained model should be saved successfully");
+    }
+    Ok(())
+}
+
+//The checkpointed model with the lowest mean loss on the valid split, with its epoch and loss.
+fn best_checkpoint<B: Backend>(
+    artifact_dir: &str,
+    config: &TrainingConfig,
+    valid_dataset: SyscallsDataset,
+    batcher: SyscallBatcher<B>,
+    device: &B::Device,
+) -> anyhow::Result<(usize, f64, Model<B>)> {
+    let dataloader = DataLoaderBuilder::new(batcher)
+        .batch_size(config.batch_size)
+        .num_workers(config.num_workers)
+        .build(valid_dataset);
+
+    let mut best: Option<(usize, f64, Model<B>)> = None;
+    for entry in fs::read_dir(Path::new(artifact_dir).join("checkpoint"))? {
+        let path = entry?.path();
+        //Checkpoints are saved as model-<epoch>.mpk next to the optimizer and scheduler states.
+        let Some(epoch) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str()?.strip_prefix("model-")?.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        let record = CompactRecorder::new().load(path.with_extension(""), device)?;
+        let model = config.model.init::<B>(device).load_record(record);
+
+        let (mut total, mut count) = (0.0, 0);
+        for batch in dataloader.iter() {
+            let size = batch.syscalls.dims()[0];
+            let loss = model.forward_reconstruction(batch.syscalls).loss.into_scalar().to_f64();
+            total += loss * size as f64;
+            count += size;
+        }
+        let loss = total / count.max(1) as f64;
+        if best.as_ref().is_none_or(|(_, best_loss, _)| loss < *best_loss) {
+            best = Some((epoch, loss, model));
+        }
+    }
+    best.ok_or_else(|| anyhow::anyhow!("no checkpoint in {artifact_dir}"))
 }
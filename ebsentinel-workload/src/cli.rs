@@ -0,0 +1,20 @@
+use clap::Parser;
+
+use crate::workload::Phase;
+
+#[derive(Parser)]
+pub struct Cli{
+    /// Phases to run in order, as KIND[:SECONDS] where KIND is one of
+    /// file-io, network, fork-exec, mixed or idle
+    #[arg(value_name = "PHASE", required = true)]
+    pub phases: Vec<Phase>,
+    /// Operations per second during each phase
+    #[arg(short, long, default_value_t = 100)]
+    pub rate: u32,
+    /// How many times to run the phases, 0 runs them forever
+    #[arg(long, default_value_t = 1)]
+    pub repeat: u32,
+    /// Seconds to wait before the first phase, leaving time to attach a recorder
+    #[arg(long, default_value_t = 0)]
+    pub delay: u64,
+}
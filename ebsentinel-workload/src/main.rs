@@ -0,0 +1,27 @@
+use std::{thread::sleep, time::Duration};
+
+use clap::Parser;
+use cli::Cli;
+use workload::Workload;
+mod cli;
+mod workload;
+
+//Generates scripted syscall mixes to record or to test the detector against.
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    println!("pid: {}", std::process::id());
+
+    let workload = Workload::new()?;
+    sleep(Duration::from_secs(cli.delay));
+
+    let mut iteration = 0;
+    while cli.repeat == 0 || iteration < cli.repeat {
+        for phase in &cli.phases {
+            println!("{phase}");
+            workload.run(phase, cli.rate)?;
+        }
+        iteration += 1;
+    }
+
+    Ok(())
+}
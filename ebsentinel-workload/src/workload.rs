@@ -0,0 +1,158 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    path::PathBuf,
+    process::{Command, Stdio},
+    str::FromStr,
+    thread::{self, sleep},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// Create, write, sync, read back and remove a small file.
+    FileIo,
+    /// Round trip a message over loopback TCP and UDP.
+    Network,
+    /// Fork and exec a short lived `true` process.
+    ForkExec,
+    /// Rotate through all the other kinds.
+    Mixed,
+    /// Only sleep.
+    Idle,
+}
+
+#[derive(Debug, Clone)]
+pub struct Phase {
+    pub kind: Kind,
+    pub duration: Duration,
+}
+
+impl FromStr for Phase {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (kind, secs) = match s.split_once(':') {
+            Some((kind, secs)) => (kind, secs.parse::<u64>()?),
+            None => (s, 10),
+        };
+        let kind = match kind {
+            "file-io" => Kind::FileIo,
+            "network" => Kind::Network,
+            "fork-exec" => Kind::ForkExec,
+            "mixed" => Kind::Mixed,
+            "idle" => Kind::Idle,
+            _ => return Err(anyhow!("unknown phase kind {kind}")),
+        };
+        Ok(Self {
+            kind,
+            duration: Duration::from_secs(secs),
+        })
+    }
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?} for {}s", self.kind, self.duration.as_secs())
+    }
+}
+
+pub struct Workload {
+    scratch: PathBuf,
+    echo_addr: SocketAddr,
+    udp: UdpSocket,
+}
+
+impl Workload {
+    pub fn new() -> anyhow::Result<Self> {
+        let scratch = std::env::temp_dir().join(format!("ebsentinel-workload-{}", std::process::id()));
+
+        //Echo server on loopback, its syscalls are part of the workload too.
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let echo_addr = listener.local_addr()?;
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = echo(stream);
+            }
+        });
+
+        let udp = UdpSocket::bind("127.0.0.1:0")?;
+        Ok(Self {
+            scratch,
+            echo_addr,
+            udp,
+        })
+    }
+
+    /// Run `phase`, issuing `rate` operations per second.
+    pub fn run(&self, phase: &Phase, rate: u32) -> anyhow::Result<()> {
+        let interval = Duration::from_secs(1) / rate.max(1);
+        let end = Instant::now() + phase.duration;
+        let mut op = 0;
+        while Instant::now() < end {
+            let kind = match phase.kind {
+                Kind::Mixed => [Kind::FileIo, Kind::Network, Kind::ForkExec][op % 3],
+                kind => kind,
+            };
+            match kind {
+                Kind::FileIo => self.file_io()?,
+                Kind::Network => self.network()?,
+                Kind::ForkExec => self.fork_exec()?,
+                Kind::Mixed | Kind::Idle => {}
+            }
+            op += 1;
+            sleep(interval);
+        }
+        Ok(())
+    }
+
+    fn file_io(&self) -> io::Result<()> {
+        let mut file = fs::File::create(&self.scratch)?;
+        file.write_all(&[0xeb; 4096])?;
+        file.sync_all()?;
+        let mut buf = Vec::new();
+        fs::File::open(&self.scratch)?.read_to_end(&mut buf)?;
+        fs::metadata(&self.scratch)?;
+        fs::remove_file(&self.scratch)
+    }
+
+    fn network(&self) -> io::Result<()> {
+        let mut stream = TcpStream::connect(self.echo_addr)?;
+        let mut buf = [0u8; 64];
+        stream.write_all(&buf)?;
+        stream.read_exact(&mut buf)?;
+
+        self.udp.send_to(&buf, self.udp.local_addr()?)?;
+        self.udp.recv_from(&mut buf)?;
+        Ok(())
+    }
+
+    fn fork_exec(&self) -> io::Result<()> {
+        Command::new("true")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .status()?;
+        Ok(())
+    }
+}
+
+impl Drop for Workload {
+    fn drop(&mut self) {
+        fs::remove_file(&self.scratch).ok();
+    }
+}
+
+fn echo(mut stream: TcpStream) -> io::Result<()> {
+    let mut buf = [0u8; 64];
+    loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        stream.write_all(&buf[..n])?;
+    }
+}
@@ -0,0 +1,12 @@
+//The burn backend the model runs on, chosen by the cargo features of this crate.
+autoencoder::select_backend!();
+
+#[cfg(test)]
+mod test {
+    //ebsentinel-train enables autoencoder/wgpu in workspace builds, the detector must stay on ndarray.
+    #[test]
+    #[cfg(all(feature = "ndarray", not(any(feature = "wgpu", feature = "candle", feature = "tch"))))]
+    fn runs_on_ndarray_by_default() {
+        assert_eq!(super::NAME, "ndarray");
+    }
+}
@@ -1,9 +1,55 @@
-use clap::Parser;
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::{Args, Parser, Subcommand};
 
 #[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli{
-    #[arg(value_name = "PID")]
-    pub pid: u32,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub detect: DetectArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Check the kernel, privileges and inference backend needed to run ebsentinel
+    #[command(alias = "check")]
+    Doctor,
+}
+
+#[derive(Args)]
+pub struct DetectArgs{
+    #[arg(value_name = "PID", required_unless_present = "command", conflicts_with = "command")]
+    pub pid: Option<u32>,
+    /// Anomaly threshold overriding the one calibrated by ebsentinel-train
     #[arg(value_name = "THRESH")]
-    pub threshold: f32,
-}
\ No newline at end of file
+    pub thresh: Option<f32>,
+    /// Same as THRESH, for use with a COMMAND
+    #[arg(long = "threshold", value_name = "THRESH", conflicts_with = "thresh")]
+    pub threshold: Option<f32>,
+    /// Directory of the runs trained by ebsentinel-train
+    #[arg(long, value_name = "DIR", default_value = "experiment")]
+    pub artifact_dir: PathBuf,
+    /// Run whose model is used: a name, a tag, latest or promoted [default: promoted, else latest]
+    #[arg(long, value_name = "RUN")]
+    pub model: Option<String>,
+    /// Public key whose signatures are trusted, see ebsentinel-train keygen, can be repeated
+    #[arg(long = "trusted-key", value_name = "FILE")]
+    pub trusted_keys: Vec<PathBuf>,
+    /// Refuse a model that is not signed by a trusted key, or was modified since it was signed
+    #[arg(long, requires = "trusted_keys")]
+    pub require_signature: bool,
+    /// Share the sensor pinned under DIR, loading and pinning it first if needed
+    #[arg(long, value_name = "DIR", num_args = 0..=1, default_missing_value = ebsentinel_core::sensor::DEFAULT_PIN_PATH)]
+    pub pin: Option<PathBuf>,
+    /// Serve Prometheus/OpenMetrics metrics on http://ADDR/metrics
+    #[arg(long, value_name = "ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Number of busiest syscalls whose rate is exported
+    #[arg(long, value_name = "N", default_value_t = 10)]
+    pub metrics_top: usize,
+    /// Launch COMMAND, monitor it from its first syscall until it exits, then exit with its status
+    #[arg(last = true, value_name = "COMMAND")]
+    pub command: Vec<String>,
+}
@@ -0,0 +1,44 @@
+use std::panic;
+
+use anyhow::anyhow;
+use burn::tensor::Tensor;
+use ebsentinel_core::check::{run_checks, Check};
+
+use crate::backend;
+
+/// Print every environment check and fail if any of them failed.
+pub fn run() -> anyhow::Result<()> {
+    let mut checks = run_checks();
+    checks.push(inference());
+
+    for check in &checks {
+        let status = if check.passed { "PASS" } else { "FAIL" };
+        println!("[{status}] {}: {}", check.name, check.detail);
+    }
+
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    if failed > 0 {
+        return Err(anyhow!("{failed} of {} checks failed", checks.len()));
+    }
+    Ok(())
+}
+
+fn inference() -> Check {
+    //wgpu panics when it cannot find an adapter, keep the report readable.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let result = panic::catch_unwind(|| {
+        let device = backend::device();
+        Tensor::<backend::Backend, 1>::zeros([1], &device).into_data()
+    });
+    panic::set_hook(hook);
+
+    match result {
+        Ok(_) => Check::pass("inference", format!("{} backend available", backend::NAME)),
+        Err(_) if backend::NAME == "wgpu" => Check::fail(
+            "inference",
+            "no wgpu adapter found, inference needs a GPU with a Vulkan, Metal or DX12 driver, or a build with the ndarray feature",
+        ),
+        Err(_) => Check::fail("inference", format!("the {} backend cannot run", backend::NAME)),
+    }
+}
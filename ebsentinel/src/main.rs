@@ -1,80 +1,147 @@
-use autoencoder::{data::Syscalls, Autoencoder, AutoencoderConfig, Model};
-use burn::{backend::Wgpu, config::Config, module::Module, optim::AdamConfig, prelude::Backend, record::{CompactRecorder, Recorder}};
+use std::{fs, sync::Arc};
+
+use anyhow::{bail, Context};
+use autoencoder::{
+    bundle::{Bundle, BUNDLE},
+    data::Syscalls,
+    registry::Registry,
+    signing::{key_id, load_verifying_key, verify_bundle},
+    Autoencoder,
+};
 use clap::Parser;
-use cli::Cli;
-use ebsentinel_core::{self, run_ebsentinel_ebpf};
+use cli::{Cli, Command, DetectArgs};
+use ebsentinel_common::{syscalls::syscall_name, MAX_SYSCALLS};
+use ebsentinel_core::{self, launch::{exit_code, Launched}, run_ebsentinel_ebpf};
+use ebsentinel_features::pipeline::Pipeline;
+use metrics::Metrics;
 use tokio::signal;
+mod backend;
 mod cli;
-#[derive(Config, Debug)]
-pub struct ModelConfig{
-    input_size: usize, 
-    latent_size: usize
-}
-
-impl ModelConfig {
-    pub fn init<B: Backend>(&self, device: &B::Device) -> Model<B> {
-        let inner=AutoencoderConfig::new(self.input_size, self.latent_size).init(device);
-        Model { inner }
-    }
-}
+mod doctor;
+mod metrics;
 
-#[derive(Config)]
-pub struct TrainingConfig {
-    pub model: ModelConfig,
-    pub optimizer: AdamConfig,
-    #[config(default = 200)]
-    pub num_epochs: usize,
-    #[config(default = 8)]
-    pub batch_size: usize,
-    #[config(default = 4)]
-    pub num_workers: usize,
-    #[config(default = 42)]
-    pub seed: u64,
-    #[config(default = 1.0e-4)]
-    pub learning_rate: f64,
-}
-
-//Main program uses the previusly trained model to detect anomalies
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
-    type MyBackend = Wgpu<f32, i32>;
-    let device = burn::backend::wgpu::WgpuDevice::default();
-    let mut proc_mon=run_ebsentinel_ebpf(cli.pid).unwrap();
-
-    let mut rx =proc_mon.run().unwrap();
+    match cli.command {
+        Some(Command::Doctor) => doctor::run(),
+        None => detect(cli.detect).await,
+    }
+}
 
-    let artifact_dir = "experiment";
-    //TODO Load only model since TrainingConfig is useless
-    let config = TrainingConfig::load(format!("{artifact_dir}/config.json"))
-    .expect("Config should exist for the model");
+//Main program uses the previusly trained model to detect anomalies
+async fn detect(args: DetectArgs) -> anyhow::Result<()> {
+    let registry = Registry::open(&args.artifact_dir)?;
+    //Models trained before the registry have no bundle, so no manifest to check them against the host.
+    let Some(run) = registry.select(args.model.as_deref())? else {
+        bail!(
+            "{} holds a model trained before model bundles, retrain it with ebsentinel-train to bundle it",
+            args.artifact_dir.display()
+        );
+    };
+    println!("using the model of run {}", run.name);
+    let path = registry.dir(&run.name).join(BUNDLE);
+    //The bundle is read once, so the model loaded is the one whose signature was verified.
+    let bytes = fs::read(&path).with_context(|| format!("cannot read {}", path.display()))?;
+    let trusted = args.trusted_keys.iter().map(|path| load_verifying_key(path)).collect::<anyhow::Result<Vec<_>>>()?;
+    match verify_bundle(&path, &bytes, &trusted) {
+        Ok(index) => println!("model signed by key {} ({})", key_id(&trusted[index]), args.trusted_keys[index].display()),
+        Err(e) if args.require_signature => return Err(e.context("refusing the model")),
+        Err(e) => eprintln!("warning: {e:#}"),
+    }
+    type MyBackend = backend::Backend;
+    let device = backend::device();
+    //Refuse a model whose features would not mean the same on this host.
+    let Bundle { manifest, model } =
+        Bundle::<MyBackend>::from_bytes(&bytes, &device).with_context(|| format!("invalid model bundle {}", path.display()))?;
+    manifest.check(MAX_SYSCALLS as usize)?;
+    let threshold = match args.thresh.or(args.threshold) {
+        Some(threshold) => threshold,
+        None => {
+            let calibration = &manifest.calibration;
+            println!(
+                "threshold {} calibrated with {} on {} {} samples",
+                calibration.threshold, calibration.strategy, calibration.scores.count, calibration.split
+            );
+            calibration.threshold
+        }
+    };
+    //A launched command is registered while it is stopped, so not a single syscall is missed.
+    let launched = match args.pid {
+        Some(_) => None,
+        None => Some(Launched::spawn_stopped(&args.command)?),
+    };
+    let pid = args.pid.or(launched.as_ref().map(Launched::pid)).unwrap();
+    let mut proc_mon=run_ebsentinel_ebpf(pid, args.pin.as_deref())?;
 
-    let record = CompactRecorder::new()
-        .load(format!("{artifact_dir}/model").into(), &device)
-        .expect("Trained model should exist");
+    let mut rx =proc_mon.run()?;
 
+    let metrics = Arc::new(Metrics::new(args.metrics_top));
+    metrics.add_target(pid, threshold, proc_mon.drop_counter());
+    if let Some(addr) = args.metrics_addr {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr, metrics).await {
+                eprintln!("metrics endpoint stopped: {e}");
+            }
+        });
+    }
 
-    let model = config.model.init::<MyBackend>(&device).load_record(record);
-    
-    tokio::spawn(async move {
-        
-        loop {
-            let rates=rx.recv().await.unwrap();
-            let item= Syscalls { counts: rates };
+    let child = launched.map(Launched::resume).transpose()?;
+    //Models trained on features derived from the raw counters see the same features here.
+    let mut pipeline = manifest.preprocessing.map(|preprocessing| Pipeline::new(preprocessing, proc_mon.polling_rate()));
+    let selection = manifest.selection;
+    let scoring = tokio::spawn(async move {
+        while let Some(sample) = rx.recv().await {
+            let features = match pipeline.as_mut() {
+                Some(pipeline) => match pipeline.push(sample.timestamp_ns, &sample.counts) {
+                    Some(features) => features,
+                    None => continue,
+                },
+                None => sample.features,
+            };
+            //Syscalls dropped by the feature selection are never seen by the model, their activity is an alert of its own.
+            let (counts, unseen) = match &selection {
+                Some(selection) => (selection.apply(&features), selection.unseen(&features)),
+                None => (features, Vec::new()),
+            };
+            if !unseen.is_empty() {
+                let names: Vec<String> = unseen.iter().map(|nr| syscall_name(*nr).map_or(nr.to_string(), str::to_string)).collect();
+                println!("unseen syscalls: {}", names.join(", "));
+            }
+            let item= Syscalls { counts };
             //Infer
             let (_, loss) = Autoencoder::infer(device.clone(), &model.inner, item);
             println!("{}",loss);
             
-            if loss > cli.threshold {
+            let anomaly = loss > threshold;
+            if anomaly {
                 println!("anomaly detected")
             }
+            metrics.observe(pid, loss, anomaly, &sample.rates, &unseen);
         }
     });
-    let ctrl_c = signal::ctrl_c();
-    println!("Waiting for Ctrl-C...");
-    ctrl_c.await?;
-    println!("Exiting...");
+    let Some(mut child) = child else {
+        let ctrl_c = signal::ctrl_c();
+        println!("Waiting for Ctrl-C...");
+        ctrl_c.await?;
+        println!("Exiting...");
+        return Ok(());
+    };
 
-    Ok(())
+    //Ctrl-C also reaches the launched command, the detector stops when it exits.
+    let status = tokio::select! {
+        status = &mut child => status??,
+        result = signal::ctrl_c() => {
+            result?;
+            child.await??
+        }
+    };
+    //The syscalls of the command since the last poll, its exit included, are scored too.
+    proc_mon.finish();
+    scoring.await?;
+    println!("Exiting...");
+    drop(proc_mon);
+    std::process::exit(exit_code(status));
 }
 
@@ -0,0 +1,245 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use ebsentinel_common::syscalls::syscall_name;
+use ebsentinel_core::sensor::{ProgramStats, Sensor};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time::sleep,
+};
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+//Wait after a failed accept, e.g. when out of file descriptors, before accepting again.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+//Syscalls are labelled like the dataset columns: by name, else `syscall_<nr>`.
+fn syscall_label(nr: usize) -> String {
+    syscall_name(nr).map_or_else(|| format!("syscall_{nr}"), str::to_string)
+}
+
+/// Detector state exported in the OpenMetrics text format.
+pub struct Metrics {
+    //Only the top_n syscalls by rate of every target are exported, to bound cardinality.
+    top_n: usize,
+    targets: Mutex<BTreeMap<u32, Target>>,
+}
+
+struct Target {
+    score: f32,
+    threshold: f32,
+    samples: u64,
+    alerts: u64,
+    rates: Vec<f32>,
+    dropped: Arc<AtomicU64>,
+    //Samples in which each syscall dropped by the feature selection was called.
+    unseen: BTreeMap<usize, u64>,
+}
+
+impl Metrics {
+    pub fn new(top_n: usize) -> Self {
+        Self {
+            top_n,
+            targets: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn add_target(&self, pid: u32, threshold: f32, dropped: Arc<AtomicU64>) {
+        self.targets.lock().unwrap().insert(
+            pid,
+            Target {
+                score: 0.0,
+                threshold,
+                samples: 0,
+                alerts: 0,
+                rates: Vec::new(),
+                dropped,
+                unseen: BTreeMap::new(),
+            },
+        );
+    }
+
+    /// Record the score of a new sample of `pid`, and the syscalls it calls that the model has never seen.
+    pub fn observe(&self, pid: u32, score: f32, alert: bool, rates: &[f32], unseen: &[usize]) {
+        if let Some(target) = self.targets.lock().unwrap().get_mut(&pid) {
+            target.score = score;
+            target.samples += 1;
+            target.alerts += alert as u64;
+            target.rates = rates.to_vec();
+            for syscall in unseen {
+                *target.unseen.entry(*syscall).or_default() += 1;
+            }
+        }
+    }
+
+    pub fn render(&self, program: Option<ProgramStats>) -> String {
+        let targets = self.targets.lock().unwrap();
+        let mut out = String::new();
+
+        family(&mut out, "ebsentinel_anomaly_score", "gauge", "Reconstruction loss of the last sample.");
+        for (pid, target) in targets.iter() {
+            writeln!(out, "ebsentinel_anomaly_score{{pid=\"{pid}\"}} {}", target.score).unwrap();
+        }
+        family(&mut out, "ebsentinel_threshold", "gauge", "Score above which a sample is an anomaly.");
+        for (pid, target) in targets.iter() {
+            writeln!(out, "ebsentinel_threshold{{pid=\"{pid}\"}} {}", target.threshold).unwrap();
+        }
+        family(&mut out, "ebsentinel_samples", "counter", "Samples scored.");
+        for (pid, target) in targets.iter() {
+            writeln!(out, "ebsentinel_samples_total{{pid=\"{pid}\"}} {}", target.samples).unwrap();
+        }
+        family(&mut out, "ebsentinel_alerts", "counter", "Samples scored above the threshold.");
+        for (pid, target) in targets.iter() {
+            writeln!(out, "ebsentinel_alerts_total{{pid=\"{pid}\"}} {}", target.alerts).unwrap();
+        }
+        family(
+            &mut out,
+            "ebsentinel_samples_dropped",
+            "counter",
+            "Samples dropped because the detector did not keep up.",
+        );
+        for (pid, target) in targets.iter() {
+            let dropped = target.dropped.load(Ordering::Relaxed);
+            writeln!(out, "ebsentinel_samples_dropped_total{{pid=\"{pid}\"}} {dropped}").unwrap();
+        }
+        family(
+            &mut out,
+            "ebsentinel_unseen_syscall_samples",
+            "counter",
+            "Samples calling a syscall the model was not trained on, by syscall.",
+        );
+        for (pid, target) in targets.iter() {
+            for (syscall, samples) in &target.unseen {
+                writeln!(out, "ebsentinel_unseen_syscall_samples_total{{pid=\"{pid}\",syscall=\"{}\"}} {samples}", syscall_label(*syscall)).unwrap();
+            }
+        }
+        family(
+            &mut out,
+            "ebsentinel_syscall_rate",
+            "gauge",
+            "Syscalls per second of the busiest syscalls in the last sample.",
+        );
+        for (pid, target) in targets.iter() {
+            let mut rates: Vec<(usize, f32)> = target.rates.iter().copied().enumerate().filter(|(_, rate)| *rate > 0.0).collect();
+            rates.sort_by(|a, b| b.1.total_cmp(&a.1));
+            for (syscall, rate) in rates.into_iter().take(self.top_n) {
+                writeln!(out, "ebsentinel_syscall_rate{{pid=\"{pid}\",syscall=\"{}\"}} {rate}", syscall_label(syscall)).unwrap();
+            }
+        }
+
+        if let Some(program) = program {
+            family(&mut out, "ebsentinel_programs_loaded", "gauge", "Sensor programs loaded in the kernel.");
+            writeln!(out, "ebsentinel_programs_loaded {}", program.programs).unwrap();
+            family(
+                &mut out,
+                "ebsentinel_program_runs",
+                "counter",
+                "Sensor program runs, only counted while kernel.bpf_stats_enabled is set.",
+            );
+            writeln!(out, "ebsentinel_program_runs_total {}", program.run_count).unwrap();
+            family(
+                &mut out,
+                "ebsentinel_program_run_seconds",
+                "counter",
+                "Time spent in the sensor programs, only counted while kernel.bpf_stats_enabled is set.",
+            );
+            writeln!(out, "ebsentinel_program_run_seconds_total {}", program.run_time.as_secs_f64()).unwrap();
+        }
+
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# TYPE {name} {kind}").unwrap();
+    writeln!(out, "# HELP {name} {help}").unwrap();
+}
+
+/// Serve `metrics` on `http://addr/metrics`, only returns if `addr` cannot be bound.
+pub async fn serve(addr: SocketAddr, metrics: Arc<Metrics>) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("metrics endpoint cannot accept a connection: {e}");
+                sleep(ACCEPT_BACKOFF).await;
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..n]);
+            let response = match request.split_whitespace().nth(1) {
+                Some("/metrics") => {
+                    let body = metrics.render(Sensor::program_stats().ok());
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                }
+                _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+            };
+            stream.write_all(response.as_bytes()).await.ok();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashSet,
+        sync::{atomic::AtomicU64, Arc},
+    };
+
+    use ebsentinel_common::syscalls::syscall_name;
+
+    use super::Metrics;
+
+    #[test]
+    fn renders_openmetrics() {
+        let metrics = Metrics::new(2);
+        metrics.add_target(7, 0.5, Arc::new(AtomicU64::new(3)));
+        let mut rates = vec![0.0; 512];
+        (rates[0], rates[1], rates[2], rates[511]) = (1.0, 4.0, 2.0, 3.0);
+        metrics.observe(7, 0.2, false, &rates, &[511]);
+        metrics.observe(7, 0.9, true, &rates, &[511]);
+        let out = metrics.render(None);
+
+        assert!(out.ends_with("# EOF\n"));
+        let counters: HashSet<&str> = out.lines().filter_map(|line| line.strip_prefix("# TYPE ")?.strip_suffix(" counter")).collect();
+        for line in out.lines().filter(|line| !line.starts_with('#')) {
+            let name = line.split(['{', ' ']).next().unwrap();
+            if let Some(family) = name.strip_suffix("_total") {
+                assert!(counters.contains(family), "{line}");
+            } else {
+                assert!(!counters.contains(name), "{line}");
+            }
+        }
+        assert!(out.contains("ebsentinel_samples_total{pid=\"7\"} 2\n"));
+        assert!(out.contains("ebsentinel_alerts_total{pid=\"7\"} 1\n"));
+        assert!(out.contains("ebsentinel_samples_dropped_total{pid=\"7\"} 3\n"));
+        assert!(out.contains("ebsentinel_unseen_syscall_samples_total{pid=\"7\",syscall=\"syscall_511\"} 2\n"));
+
+        //Only the 2 busiest syscalls, named after the host's syscalls.
+        let rates: Vec<&str> = out.lines().filter(|line| line.starts_with("ebsentinel_syscall_rate{")).collect();
+        assert_eq!(
+            rates,
+            [
+                format!("ebsentinel_syscall_rate{{pid=\"7\",syscall=\"{}\"}} 4", syscall_name(1).unwrap_or("syscall_1")),
+                "ebsentinel_syscall_rate{pid=\"7\",syscall=\"syscall_511\"} 3".to_string(),
+            ]
+        );
+    }
+}